@@ -0,0 +1,258 @@
+use crate::config::Source;
+use crate::datasource::{CtxDataType, CtxErrorType, DataSourceRows};
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the cache directory, next to the config file
+pub const CACHE_DIR: &str = ".scg-cache";
+
+/// Changed when the readers produce different rows, so that older cache entries are not used
+const CACHE_FORMAT: &str = "2";
+
+/// On-disk cache of the rows read from sources, so that unchanged files are not parsed again
+#[derive(Debug)]
+pub struct SourceCache {
+    dir: PathBuf,
+}
+
+/// Cell value with the type kept, so that a cached row is identical to a fresh read
+#[derive(Debug, Serialize, Deserialize)]
+enum CachedValue {
+    Int(i64),
+    /// Bits of the f64, to round-trip every value exactly
+    Float(u64),
+    String(String),
+    Bool(bool),
+    DateTime(u64),
+    Error(CtxErrorType),
+    Empty,
+    List(Vec<CachedValue>),
+    Map(IndexMap<String, CachedValue>),
+}
+
+impl From<&CtxDataType> for CachedValue {
+    fn from(value: &CtxDataType) -> Self {
+        match value {
+            CtxDataType::Int(i) => Self::Int(*i),
+            CtxDataType::Float(f) => Self::Float(f.to_bits()),
+            CtxDataType::String(s) => Self::String(s.clone()),
+            CtxDataType::Bool(b) => Self::Bool(*b),
+            CtxDataType::DateTime(d) => Self::DateTime(d.to_bits()),
+            CtxDataType::Error(e) => Self::Error(e.clone()),
+            CtxDataType::Empty => Self::Empty,
+            CtxDataType::List(values) => Self::List(values.iter().map(Self::from).collect()),
+            CtxDataType::Map(values) => Self::Map(
+                values
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<CachedValue> for CtxDataType {
+    fn from(value: CachedValue) -> Self {
+        match value {
+            CachedValue::Int(i) => Self::Int(i),
+            CachedValue::Float(bits) => Self::Float(f64::from_bits(bits)),
+            CachedValue::String(s) => Self::String(s),
+            CachedValue::Bool(b) => Self::Bool(b),
+            CachedValue::DateTime(bits) => Self::DateTime(f64::from_bits(bits)),
+            CachedValue::Error(e) => Self::Error(e),
+            CachedValue::Empty => Self::Empty,
+            CachedValue::List(values) => Self::List(values.into_iter().map(Self::from).collect()),
+            CachedValue::Map(values) => Self::Map(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// A cache file holds the rows of one source, with the key they were read with
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    rows: IndexMap<String, HashMap<String, CachedValue>>,
+}
+
+impl SourceCache {
+    pub fn new(relative_root: &Path) -> Self {
+        Self {
+            dir: relative_root.join(CACHE_DIR),
+        }
+    }
+
+    /// Hash of everything that affects how a source is read: the scg version, the reader options
+    /// and the content of each file
+    pub fn key(source: &Source, relative_root: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(CACHE_FORMAT);
+        let options = format!(
+            "{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+            source.table_layout(),
+            source.sheet,
+            source.delimiter,
+            source.query,
+            source.combine,
+            source.on_cell_error,
+        );
+        hasher.update(options.len().to_le_bytes());
+        hasher.update(options);
+        for filename in source.filename.to_vec() {
+            let path = relative_root.join(filename);
+            let content = fs::read(&path)
+                .with_context(|| format!("Problem reading source file '{}'", path.display()))?;
+            hasher.update(filename.len().to_le_bytes());
+            hasher.update(filename);
+            hasher.update(content.len().to_le_bytes());
+            hasher.update(&content);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    fn path(&self, source_id: &str) -> PathBuf {
+        let name: String = source_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.dir.join(format!("{name}.json"))
+    }
+
+    /// The cached rows for the source, if they were stored with the same key
+    pub fn load(&self, source_id: &str, key: &str) -> Option<DataSourceRows> {
+        let content = fs::read(self.path(source_id)).ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&content).ok()?;
+        (entry.key == key).then(|| {
+            entry
+                .rows
+                .into_iter()
+                .map(|(row_key, row)| {
+                    let row = row
+                        .into_iter()
+                        .map(|(column, value)| (column, CtxDataType::from(value)))
+                        .collect();
+                    (row_key, row)
+                })
+                .collect()
+        })
+    }
+
+    /// Store the rows for the source, replacing any earlier entry for it
+    pub fn store(&self, source_id: &str, key: &str, rows: &DataSourceRows) -> Result<()> {
+        let entry = CacheEntry {
+            key: key.to_string(),
+            rows: rows
+                .iter()
+                .map(|(row_key, row)| {
+                    let row = row
+                        .iter()
+                        .map(|(column, value)| (column.clone(), CachedValue::from(value)))
+                        .collect();
+                    (row_key.clone(), row)
+                })
+                .collect(),
+        };
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!("Problem creating cache directory '{}'", self.dir.display())
+        })?;
+        let path = self.path(source_id);
+        fs::write(&path, serde_json::to_vec(&entry)?)
+            .with_context(|| format!("Problem writing cache file '{}'", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> DataSourceRows {
+        let mut row = HashMap::new();
+        row.insert("int".to_string(), CtxDataType::Int(-3));
+        row.insert("float".to_string(), CtxDataType::Float(0.1 + 0.2));
+        row.insert("nan".to_string(), CtxDataType::Float(f64::NAN));
+        row.insert("date".to_string(), CtxDataType::DateTime(45443.5));
+        row.insert("text".to_string(), CtxDataType::String("1.0".to_string()));
+        row.insert("bool".to_string(), CtxDataType::Bool(true));
+        row.insert("error".to_string(), CtxDataType::Error(CtxErrorType::NA));
+        row.insert("empty".to_string(), CtxDataType::Empty);
+        row.insert(
+            "nested".to_string(),
+            CtxDataType::List(vec![CtxDataType::Map(IndexMap::from([(
+                "b".to_string(),
+                CtxDataType::Int(1),
+            )]))]),
+        );
+        IndexMap::from([
+            ("second".to_string(), row.clone()),
+            ("first".to_string(), row),
+        ])
+    }
+
+    #[test]
+    fn cache_round_trips_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = SourceCache::new(dir.path());
+        let rows = rows();
+        cache.store("main/wells", "abc", &rows).unwrap();
+
+        let cached = cache.load("main/wells", "abc").unwrap();
+        assert_eq!(cached.keys().collect::<Vec<_>>(), ["second", "first"]);
+        for (row_key, row) in &rows {
+            for (column, value) in row {
+                match value {
+                    CtxDataType::Float(f) if f.is_nan() => assert!(matches!(
+                        cached[row_key][column],
+                        CtxDataType::Float(f) if f.is_nan()
+                    )),
+                    _ => assert_eq!(&cached[row_key][column], value, "{column}"),
+                }
+            }
+        }
+        assert!(dir.path().join(CACHE_DIR).join("main_wells.json").exists());
+    }
+
+    #[test]
+    fn cache_misses_on_other_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = SourceCache::new(dir.path());
+        assert!(cache.load("wells", "abc").is_none());
+        cache.store("wells", "abc", &rows()).unwrap();
+        assert!(cache.load("wells", "def").is_none());
+        fs::write(dir.path().join(CACHE_DIR).join("wells.json"), "{").unwrap();
+        assert!(cache.load("wells", "abc").is_none());
+    }
+
+    #[test]
+    fn key_changes_with_content_and_options() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;100\n").unwrap();
+        let source = Source {
+            filename: "wells.csv".into(),
+            id: "wells".to_string(),
+            ..Default::default()
+        };
+        let key = SourceCache::key(&source, dir.path()).unwrap();
+        assert_eq!(key, SourceCache::key(&source, dir.path()).unwrap());
+
+        let other_delimiter = Source {
+            filename: "wells.csv".into(),
+            id: "wells".to_string(),
+            delimiter: Some(','),
+            ..Default::default()
+        };
+        assert_ne!(key, SourceCache::key(&other_delimiter, dir.path()).unwrap());
+
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;200\n").unwrap();
+        assert_ne!(key, SourceCache::key(&source, dir.path()).unwrap());
+    }
+}
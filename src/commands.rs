@@ -14,18 +14,26 @@ pub enum Commands {
     Drawio(Drawio),
     /// Dump json schema for yaml config
     Schema(Schema),
+    /// Check a Septic config for semantic errors
+    Lint(Lint),
+    /// Show which template and source row produced a line in a generated file
+    Blame(Blame),
 }
 
+mod blame;
 mod checklogs;
 mod diff;
 mod drawio;
+mod lint;
 mod make;
 mod schema;
 mod update;
 
+pub use blame::Blame;
 pub use checklogs::Checklogs;
 pub use diff::Diff;
 pub use drawio::Drawio;
+pub use lint::Lint;
 pub use make::Make;
 pub use schema::Schema;
 pub use update::Update;
@@ -0,0 +1,95 @@
+use crate::sourcemap::SourceMap;
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use colored::Colorize;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+pub struct Blame {
+    /// The generated file. Its source map (<file>.map.json) must have been written by 'scg make --sourcemap'
+    pub file: PathBuf,
+    /// Line number in the generated file
+    pub line: usize,
+}
+
+impl Blame {
+    pub fn execute(&self) {
+        match cmd_blame(&self.file, self.line) {
+            Ok(msg) => println!("{msg}"),
+            Err(err) => {
+                eprintln!("{err:#}");
+                std::process::exit(2)
+            }
+        }
+    }
+}
+
+fn cmd_blame(file: &Path, line: usize) -> Result<String> {
+    let source_map = SourceMap::read(&SourceMap::path_for(file))?;
+    let entry = source_map.entry_for_line(line).ok_or_else(|| {
+        anyhow!(
+            "Line {line} not found in source map for '{}'",
+            file.display()
+        )
+    })?;
+    let location = format!(
+        "{}[{line}]",
+        file.file_name().unwrap_or_default().to_string_lossy()
+    );
+    Ok(format!(
+        "{}: {entry} (lines {}-{})",
+        location.bright_green(),
+        entry.lines.start,
+        entry.lines.end
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::Origin;
+
+    #[test]
+    fn cmd_blame_reports_template_and_row() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("out.cnfg");
+        let origins = [
+            Origin {
+                template: "01_system.tmpl".to_string(),
+                source: None,
+                row: None,
+                offset: 0,
+            },
+            Origin {
+                template: "02_well.tmpl".to_string(),
+                source: Some("wells".to_string()),
+                row: Some("D01".to_string()),
+                offset: 3,
+            },
+        ];
+        SourceMap::new("a\r\nb\r\nc\r\n", &origins).write(&SourceMap::path_for(&file))?;
+
+        let result = cmd_blame(&file, 3)?;
+        assert!(result.contains("out.cnfg[3]"));
+        assert!(result.contains("02_well.tmpl, wells row 'D01' (lines 2-3)"));
+        assert!(
+            cmd_blame(&file, 4)
+                .unwrap_err()
+                .to_string()
+                .contains("Line 4 not found")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cmd_blame_fails_on_missing_sourcemap() {
+        let result = cmd_blame(Path::new("nonexistent.cnfg"), 1);
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("nonexistent.cnfg.map.json")
+        );
+    }
+}
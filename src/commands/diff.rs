@@ -1,7 +1,11 @@
+use crate::septic::{Attribute, Document, Object, Value};
 use anyhow::{Context, Result};
 use clap::Parser;
+use colored::Colorize;
 use diffy::{PatchFormatter, create_patch};
 use encoding_rs_io::DecodeReaderBytesBuilder;
+use indexmap::IndexMap;
+use std::fmt;
 use std::fs;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
@@ -15,11 +19,14 @@ pub struct Diff {
     /// Specify encoding for the files
     #[arg(long, default_value = "windows-1252")]
     pub encoding: String,
+    /// Compare Septic configs object by object and attribute by attribute, ignoring alignment and object order
+    #[arg(long)]
+    pub semantic: bool,
 }
 
 impl Diff {
     pub fn execute(&self) {
-        let result = cmd_diff(&self.file1, &self.file2, &self.encoding);
+        let result = cmd_diff(&self.file1, &self.file2, &self.encoding, self.semantic);
         match result {
             Ok(res) => {
                 if let Some(diff) = res {
@@ -35,7 +42,7 @@ impl Diff {
     }
 }
 
-fn cmd_diff(file1: &Path, file2: &Path, encoding: &str) -> Result<Option<String>> {
+fn cmd_diff(file1: &Path, file2: &Path, encoding: &str, semantic: bool) -> Result<Option<String>> {
     let mut file_content = [String::new(), String::new()];
 
     for (i, file) in [file1, file2].iter().enumerate() {
@@ -48,6 +55,13 @@ fn cmd_diff(file1: &Path, file2: &Path, encoding: &str) -> Result<Option<String>
         reader.read_to_string(&mut file_content[i])?;
     }
 
+    if semantic {
+        let old_doc = Document::parse(&file_content[0]).context(file1.display().to_string())?;
+        let new_doc = Document::parse(&file_content[1]).context(file2.display().to_string())?;
+        let diff = SemanticDiff::new(&old_doc, &new_doc);
+        return Ok((!diff.is_empty()).then(|| diff.to_string()));
+    }
+
     let diff = create_patch(&file_content[0], &file_content[1]);
 
     if diff.to_string().trim_end().ends_with("+++ modified") {
@@ -61,6 +75,193 @@ fn cmd_diff(file1: &Path, file2: &Path, encoding: &str) -> Result<Option<String>
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub enum AttributeChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Modified {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ObjectDiff {
+    pub object: String,
+    pub changes: Vec<AttributeChange>,
+}
+
+/// Differences between two Septic configs, independent of object order and value alignment
+#[derive(Debug, Default, PartialEq)]
+pub struct SemanticDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<ObjectDiff>,
+}
+
+impl SemanticDiff {
+    pub fn new(old: &Document, new: &Document) -> Self {
+        let old_objects = objects_by_identity(old);
+        let new_objects = objects_by_identity(new);
+        let mut diff = SemanticDiff::default();
+
+        for (identity, old_obj) in &old_objects {
+            match new_objects.get(identity) {
+                None => diff.removed.push(identity.clone()),
+                Some(new_obj) => {
+                    let changes = attribute_changes(old_obj, new_obj);
+                    if !changes.is_empty() {
+                        diff.modified.push(ObjectDiff {
+                            object: identity.clone(),
+                            changes,
+                        });
+                    }
+                }
+            }
+        }
+        for identity in new_objects.keys() {
+            if !old_objects.contains_key(identity) {
+                diff.added.push(identity.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl fmt::Display for SemanticDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for object in &self.removed {
+            writeln!(f, "{}", format!("- {object}").red())?;
+        }
+        for object in &self.added {
+            writeln!(f, "{}", format!("+ {object}").green())?;
+        }
+        for object in &self.modified {
+            writeln!(f, "{}", format!("~ {}", object.object).yellow())?;
+            for change in &object.changes {
+                let line = match change {
+                    AttributeChange::Added { key, value } => format!("+ {key}= {value}").green(),
+                    AttributeChange::Removed { key, value } => format!("- {key}= {value}").red(),
+                    AttributeChange::Modified { key, old, new } => {
+                        format!("~ {key}= {old} -> {new}").yellow()
+                    }
+                };
+                writeln!(f, "    {line}")?;
+            }
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} modified",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len()
+        )
+    }
+}
+
+/// Objects keyed on their kind and name, prefixed by those of their ancestors. Repeated
+/// identities are numbered in document order.
+fn objects_by_identity(doc: &Document) -> IndexMap<String, &Object> {
+    let mut objects = IndexMap::new();
+    for (index, obj) in doc.objects.iter().enumerate() {
+        let mut labels = Vec::new();
+        let mut current = Some(index);
+        while let Some(i) = current {
+            let obj = &doc.objects[i];
+            labels.push(format!("{} '{}'", obj.kind, obj.name));
+            current = obj.parent;
+        }
+        labels.reverse();
+        let identity = labels.join(" / ");
+        let mut unique = identity.clone();
+        let mut occurrence = 1;
+        while objects.contains_key(&unique) {
+            occurrence += 1;
+            unique = format!("{identity} #{occurrence}");
+        }
+        objects.insert(unique, obj);
+    }
+    objects
+}
+
+/// Attributes keyed on name. Repeated keys are numbered in object order.
+fn attributes_by_key(obj: &Object) -> IndexMap<String, &Attribute> {
+    let mut attributes = IndexMap::new();
+    for attr in obj.attributes() {
+        let mut unique = attr.key.clone();
+        let mut occurrence = 1;
+        while attributes.contains_key(&unique) {
+            occurrence += 1;
+            unique = format!("{} #{occurrence}", attr.key);
+        }
+        attributes.insert(unique, attr);
+    }
+    attributes
+}
+
+fn format_values(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn values_equal(old: &[Value], new: &[Value]) -> bool {
+    old.len() == new.len()
+        && old.iter().zip(new).all(|pair| match pair {
+            (Value::Int(i), Value::Float(x)) | (Value::Float(x), Value::Int(i)) => *i as f64 == *x,
+            (a, b) => a == b,
+        })
+}
+
+fn attribute_changes(old: &Object, new: &Object) -> Vec<AttributeChange> {
+    let old_attributes = attributes_by_key(old);
+    let new_attributes = attributes_by_key(new);
+    let mut changes = Vec::new();
+
+    for (key, old_attr) in &old_attributes {
+        let old_values = old_attr.values();
+        match new_attributes.get(key) {
+            None => changes.push(AttributeChange::Removed {
+                key: key.clone(),
+                value: format_values(&old_values),
+            }),
+            Some(new_attr) => {
+                let new_values = new_attr.values();
+                if !values_equal(&old_values, &new_values) {
+                    changes.push(AttributeChange::Modified {
+                        key: key.clone(),
+                        old: format_values(&old_values),
+                        new: format_values(&new_values),
+                    });
+                }
+            }
+        }
+    }
+    for (key, new_attr) in &new_attributes {
+        if !old_attributes.contains_key(key) {
+            changes.push(AttributeChange::Added {
+                key: key.clone(),
+                value: format_values(&new_attr.values()),
+            });
+        }
+    }
+    changes
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,7 +278,7 @@ mod tests {
     fn cmd_diff_fail_on_missing_file() -> Result<()> {
         let file1 = create_temp_file_with_content("File 1 content")?;
         let file2 = Path::new("nonexistent.txt");
-        let result = cmd_diff(file1.path(), file2, "windows-1252");
+        let result = cmd_diff(file1.path(), file2, "windows-1252", false);
         assert!(result.is_err());
         let error_message = result.unwrap_err().root_cause().to_string();
         assert!(error_message.contains("(os error 2)"));
@@ -88,8 +289,8 @@ mod tests {
     fn cmd_diff_returns_diff_with_colors() -> Result<()> {
         let file1 = create_temp_file_with_content("File 1 content")?;
         let file2 = create_temp_file_with_content("File 2 content")?;
-        let diff_output =
-            cmd_diff(file1.path(), file2.path(), "windows-1252")?.expect("Expected diff, got None");
+        let diff_output = cmd_diff(file1.path(), file2.path(), "windows-1252", false)?
+            .expect("Expected diff, got None");
         assert!(diff_output.contains("--- original"));
         assert!(diff_output.contains("-File 1 content"));
         assert!(diff_output.contains("+File 2 content"));
@@ -101,8 +302,91 @@ mod tests {
     fn cmd_diff_no_diff() -> Result<()> {
         let file1 = create_temp_file_with_content("File common content")?;
         let file2 = create_temp_file_with_content("File common content")?;
-        let diff_output = cmd_diff(file1.path(), file2.path(), "windows-1252")?;
+        let diff_output = cmd_diff(file1.path(), file2.path(), "windows-1252", false)?;
         assert!(diff_output.is_none());
         Ok(())
     }
+
+    const OLD_CONFIG: &str = "  SmpcAppl:      appl
+  Mvr:           A
+         MaxUp=  0.5
+          Mode=  ACTIVE
+  Cvr:           B
+          Meas=  1
+  Cvr:           C
+          Meas=  1
+";
+
+    #[test]
+    fn semantic_diff_ignores_alignment_and_order() -> Result<()> {
+        let new_config = "  SmpcAppl:  appl
+  Cvr:  B
+  Meas= 1.0
+  Mvr:  A
+  Mode=ACTIVE
+  MaxUp=    0.5
+  Cvr:  C
+  Meas=  1
+";
+        let diff = SemanticDiff::new(&Document::parse(OLD_CONFIG)?, &Document::parse(new_config)?);
+        assert!(diff.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn semantic_diff_reports_object_and_attribute_changes() -> Result<()> {
+        let new_config = "  SmpcAppl:      appl
+  Mvr:           A
+         MaxUp=  0.6
+          Text=  \"new\"
+  Cvr:           B
+          Meas=  1
+  Cvr:           D
+          Meas=  1
+";
+        let diff = SemanticDiff::new(&Document::parse(OLD_CONFIG)?, &Document::parse(new_config)?);
+        assert_eq!(diff.removed, vec!["SmpcAppl 'appl' / Cvr 'C'"]);
+        assert_eq!(diff.added, vec!["SmpcAppl 'appl' / Cvr 'D'"]);
+        assert_eq!(
+            diff.modified,
+            vec![ObjectDiff {
+                object: "SmpcAppl 'appl' / Mvr 'A'".to_string(),
+                changes: vec![
+                    AttributeChange::Modified {
+                        key: "MaxUp".to_string(),
+                        old: "0.5".to_string(),
+                        new: "0.6".to_string()
+                    },
+                    AttributeChange::Removed {
+                        key: "Mode".to_string(),
+                        value: "ACTIVE".to_string()
+                    },
+                    AttributeChange::Added {
+                        key: "Text".to_string(),
+                        value: "\"new\"".to_string()
+                    },
+                ]
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn semantic_diff_numbers_repeated_objects() -> Result<()> {
+        let old_config = "  Spacer:  s1\n  Size=  1\n  Spacer:  s1\n  Size=  2\n";
+        let new_config = "  Spacer:  s1\n  Size=  1\n  Spacer:  s1\n  Size=  3\n";
+        let diff = SemanticDiff::new(&Document::parse(old_config)?, &Document::parse(new_config)?);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].object, "Spacer 's1' #2");
+        Ok(())
+    }
+
+    #[test]
+    fn cmd_diff_semantic_no_diff_on_realignment() -> Result<()> {
+        let file1 = create_temp_file_with_content("  Mvr:  A\n  MaxUp=  0.5\n")?;
+        let file2 = create_temp_file_with_content("  Mvr:           A\n         MaxUp=  0.5\n")?;
+        assert!(cmd_diff(file1.path(), file2.path(), "windows-1252", true)?.is_none());
+        assert!(cmd_diff(file1.path(), file2.path(), "windows-1252", false)?.is_some());
+        Ok(())
+    }
 }
@@ -0,0 +1,455 @@
+use crate::septic::{Document, Span, Value};
+use crate::sourcemap::{SourceMap, SourceMapEntry};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use indexmap::IndexMap;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+const DEFAULT_RULES: &str = include_str!("lint/rules.yaml");
+
+static IDENTIFIER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b([A-Za-z_][A-Za-z0-9_]*)\b(\s*\()?").unwrap());
+
+#[derive(Parser, Debug)]
+pub struct Lint {
+    /// The Septic config file to check. Issues name the template and source row if <file>.map.json exists.
+    pub file: PathBuf,
+    /// Yaml file with rules that extend or replace the default rules per object type
+    #[arg(long, value_name = "FILE")]
+    pub rules: Option<PathBuf>,
+    /// Specify encoding for the file
+    #[arg(long, default_value = "windows-1252")]
+    pub encoding: String,
+}
+
+impl Lint {
+    pub fn execute(&self) {
+        let result = cmd_lint(&self.file, self.rules.as_deref(), &self.encoding);
+        match result {
+            Ok(issues) => {
+                for issue in &issues {
+                    println!("{issue}");
+                }
+                if !issues.is_empty() {
+                    std::process::exit(1)
+                }
+            }
+            Err(err) => {
+                eprintln!("{err:#}");
+                std::process::exit(2)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectRule {
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub required: Vec<String>,
+    pub allowed: Option<Vec<String>>,
+    #[serde(default, rename = "match")]
+    pub matches: Vec<String>,
+    #[serde(default)]
+    pub within: Vec<String>,
+    #[serde(default)]
+    pub references: IndexMap<String, Vec<String>>,
+    #[serde(default)]
+    pub expressions: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LintRules {
+    #[serde(default)]
+    pub variables: Vec<String>,
+    #[serde(default)]
+    pub objects: IndexMap<String, ObjectRule>,
+}
+
+impl LintRules {
+    /// The built-in rules, extended with the rules in `extra` if provided. Rules for an object
+    /// type in `extra` replace the built-in rules for that type.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn load(extra: Option<&Path>) -> Result<Self> {
+        let mut rules: Self = serde_yaml::from_str(DEFAULT_RULES)?;
+        if let Some(path) = extra {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Problem reading rules '{}'", path.display()))?;
+            let extra: Self = serde_yaml::from_str(&content)
+                .with_context(|| format!("Problem parsing rules '{}'", path.display()))?;
+            for variable in extra.variables {
+                if !rules.variables.contains(&variable) {
+                    rules.variables.push(variable);
+                }
+            }
+            rules.objects.extend(extra.objects);
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LintIssue {
+    pub span: Span,
+    pub message: String,
+}
+
+pub fn format_issue(file_name: &str, issue: &LintIssue, origin: Option<&SourceMapEntry>) -> String {
+    let location = format!("{file_name}[{}]", issue.span.start);
+    match origin {
+        Some(origin) => format!(
+            "{} ({}): {}",
+            location.bright_green(),
+            origin,
+            issue.message.red()
+        ),
+        None => format!("{}: {}", location.bright_green(), issue.message.red()),
+    }
+}
+
+/// Formatted issues, with the template and source row of each issue when the file has a source map
+fn cmd_lint(file: &Path, rules: Option<&Path>, encoding: &str) -> Result<Vec<String>> {
+    let rules = LintRules::load(rules)?;
+    let map_path = SourceMap::path_for(file);
+    let source_map = if map_path.exists() {
+        Some(SourceMap::read(&map_path)?)
+    } else {
+        None
+    };
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let file = fs::File::open(file).context(file.display().to_string())?;
+    let encoding =
+        encoding_rs::Encoding::for_label(encoding.as_bytes()).unwrap_or(encoding_rs::WINDOWS_1252);
+    let mut reader = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(file);
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let doc = Document::parse(&content)?;
+    Ok(lint(&doc, &rules)
+        .iter()
+        .map(|issue| {
+            let origin = source_map
+                .as_ref()
+                .and_then(|map| map.entry_for_line(issue.span.start));
+            format_issue(&file_name, issue, origin)
+        })
+        .collect())
+}
+
+fn root_of(doc: &Document, mut index: usize) -> usize {
+    while let Some(parent) = doc.objects[index].parent {
+        index = parent;
+    }
+    index
+}
+
+/// Check a parsed config against the rules. Issues are sorted by line number.
+pub fn lint(doc: &Document, rules: &LintRules) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    // Names per root object, so that references and expressions only see their own application
+    let mut names_by_kind: HashMap<(usize, &str), HashSet<&str>> = HashMap::new();
+    for (index, obj) in doc.objects.iter().enumerate() {
+        names_by_kind
+            .entry((root_of(doc, index), obj.kind.as_str()))
+            .or_default()
+            .insert(obj.name.as_str());
+    }
+    let is_defined = |root: Option<usize>, kinds: &[String], name: &str| {
+        names_by_kind.iter().any(|((index, kind), names)| {
+            root.is_none_or(|root| root == *index)
+                && kinds.iter().any(|k| k == kind)
+                && names.contains(name)
+        })
+    };
+
+    let mut seen: HashMap<(usize, &str, &str), usize> = HashMap::new();
+
+    for (index, obj) in doc.objects.iter().enumerate() {
+        let Some(rule) = rules.objects.get(&obj.kind) else {
+            continue;
+        };
+        let label = format!("{} '{}'", obj.kind, obj.name);
+        let root = root_of(doc, index);
+
+        if let Some(namespace) = &rule.namespace {
+            let key = (root, namespace.as_str(), obj.name.as_str());
+            if let Some(first) = seen.get(&key) {
+                issues.push(LintIssue {
+                    span: obj.span,
+                    message: format!(
+                        "{label}: duplicate name in namespace '{namespace}', first defined on line {first}"
+                    ),
+                });
+            } else {
+                seen.insert(key, obj.span.start);
+            }
+        }
+
+        for key in &rule.required {
+            if obj.get(key).is_none() {
+                issues.push(LintIssue {
+                    span: obj.span,
+                    message: format!("{label}: missing mandatory attribute '{key}'"),
+                });
+            }
+        }
+
+        if let Some(allowed) = &rule.allowed {
+            for attr in obj.attributes() {
+                if !allowed.contains(&attr.key) {
+                    issues.push(LintIssue {
+                        span: attr.span,
+                        message: format!("{label}: invalid attribute '{}'", attr.key),
+                    });
+                }
+            }
+        }
+
+        if !rule.matches.is_empty()
+            && (rule.within.is_empty() || rule.within.contains(&doc.objects[root].kind))
+            // Matching objects are in the process, not in the application
+            && !is_defined(None, &rule.matches, &obj.name)
+        {
+            issues.push(LintIssue {
+                span: obj.span,
+                message: format!("{label}: no matching {}", rule.matches.join(" or ")),
+            });
+        }
+
+        for (key, kinds) in &rule.references {
+            for attr in obj.attributes().filter(|attr| &attr.key == key) {
+                for value in attr.values() {
+                    if let Value::Str(name) = value
+                        && !is_defined(Some(root), kinds, &name)
+                    {
+                        issues.push(LintIssue {
+                            span: attr.span,
+                            message: format!(
+                                "{label}: '{name}' in {key} is not a defined {}",
+                                kinds.join(" or ")
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        for key in &rule.expressions {
+            for attr in obj.attributes().filter(|attr| &attr.key == key) {
+                // A list attribute holds one expression per quoted value
+                for value in attr.values() {
+                    let Value::Str(expression) = value else {
+                        continue;
+                    };
+                    for variable in expression_variables(&expression) {
+                        if !is_defined(Some(root), &rules.variables, variable) {
+                            issues.push(LintIssue {
+                                span: attr.span,
+                                message: format!(
+                                    "{label}: undefined variable '{variable}' in {key}"
+                                ),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    issues.sort_by_key(|issue| issue.span.start);
+    issues
+}
+
+/// Identifiers in an expression that are not function calls
+fn expression_variables(expression: &str) -> Vec<&str> {
+    let mut variables = Vec::new();
+    for caps in IDENTIFIER_RE.captures_iter(expression) {
+        let name = caps.get(1).unwrap().as_str();
+        if caps.get(2).is_none() && !variables.contains(&name) {
+            variables.push(name);
+        }
+    }
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::Origin;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn lint_str(content: &str) -> Vec<LintIssue> {
+        let doc = Document::parse(content).unwrap();
+        lint(&doc, &LintRules::load(None).unwrap())
+    }
+
+    #[test]
+    fn lint_detects_duplicate_names_in_namespace() {
+        let issues = lint_str(
+            "  SopcProc:  proc\n  SopcMvr:  A\n  MvrTag=  \"a\"\n  SopcCvr:  A\n  CvrTag=  \"a\"\n",
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].span.start, 4);
+        assert!(
+            issues[0]
+                .message
+                .contains("duplicate name in namespace 'sopcxvr'")
+        );
+        assert!(issues[0].message.contains("first defined on line 2"));
+    }
+
+    #[test]
+    fn lint_allows_same_name_in_different_applications() {
+        let issues = lint_str("  DmmyAppl:  one\n  Dvr:  A\n  DmmyAppl:  two\n  Dvr:  A\n");
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn lint_detects_xvr_without_sopcxvr() {
+        let issues = lint_str(
+            "  SopcProc:  proc\n  SopcMvr:  A\n  MvrTag=  \"a\"\n  SmpcAppl:  appl\n  Mvr:  A\n  Mvr:  B\n",
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].span.start, 6);
+        assert!(issues[0].message.contains("Mvr 'B': no matching SopcMvr"));
+    }
+
+    #[test]
+    fn lint_detects_undefined_expression_variables() {
+        let issues = lint_str(
+            "  SmpcAppl:  appl\n  Dvr:  A\n  CalcPvr:  C\n  Alg=  \"A + 2*abs(B) + 1e5\"\n",
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].span.start, 4);
+        assert!(issues[0].message.contains("undefined variable 'B' in Alg"));
+    }
+
+    #[test]
+    fn lint_detects_undefined_exprmodl_variables() {
+        let issues = lint_str(
+            "  SmpcAppl:  appl\n  Dvr:  A\n  ExprModl:  model\n  Expr=  2\n  \"A*2\"  \"max(A, C)\"\n",
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].span.start, 4);
+        assert!(
+            issues[0]
+                .message
+                .contains("ExprModl 'model': undefined variable 'C' in Expr")
+        );
+    }
+
+    #[test]
+    fn lint_looks_up_names_within_the_application() {
+        let issues = lint_str(
+            "  SmpcAppl:  one\n  Dvr:  A\n  Dvr:  B\n  SmpcAppl:  two\n  Dvr:  A\n  CalcPvr:  C\n  Alg=  \"A + B\"\n  DisplayGroup:  grp\n  GroupNo=  1\n  ImageMultiXvrPlot:  p\n  Xvrs=  2\n  \"A\"  \"B\"\n",
+        );
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].span.start, 7);
+        assert!(issues[0].message.contains("undefined variable 'B' in Alg"));
+        assert_eq!(issues[1].span.start, 11);
+        assert!(issues[1].message.contains("'B' in Xvrs"));
+    }
+
+    #[test]
+    fn cmd_lint_reports_origin_from_source_map() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("out.cnfg");
+        fs::write(&file, "  SmpcAppl:  appl\n  CalcPvr:  C\n  Text1=  \"\"\n")?;
+        assert_eq!(cmd_lint(&file, None, "windows-1252")?.len(), 1);
+        assert!(!cmd_lint(&file, None, "windows-1252")?[0].contains("calc.tmpl"));
+
+        let origin = |template: &str, row: Option<&str>, offset| Origin {
+            template: template.to_string(),
+            source: row.map(|_| "calcs".to_string()),
+            row: row.map(ToString::to_string),
+            offset,
+        };
+        let content = fs::read_to_string(&file)?;
+        SourceMap::new(
+            &content,
+            &[
+                origin("appl.tmpl", None, 0),
+                origin("calc.tmpl", Some("C"), 18),
+            ],
+        )
+        .write(&SourceMap::path_for(&file))?;
+        let issues = cmd_lint(&file, None, "windows-1252")?;
+        assert_eq!(issues.len(), 1);
+        assert!(
+            issues[0].contains("calc.tmpl, calcs row 'C'"),
+            "{}",
+            issues[0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lint_detects_missing_mandatory_attribute() {
+        let issues = lint_str("  SmpcAppl:  appl\n  CalcPvr:  C\n  Text1=  \"\"\n");
+        assert_eq!(issues.len(), 1);
+        assert!(
+            issues[0]
+                .message
+                .contains("missing mandatory attribute 'Alg'")
+        );
+    }
+
+    #[test]
+    fn lint_detects_undefined_references() {
+        let issues = lint_str(
+            "  SmpcAppl:  appl\n  Dvr:  A\n  DisplayGroup:  grp\n  GroupNo=  1\n  ImageMultiXvrPlot:  p\n  Xvrs=  2\n  \"A\"  \"B\"\n",
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].span.start, 6);
+        assert!(issues[0].message.contains("'B' in Xvrs"));
+    }
+
+    #[test]
+    fn lint_uses_extra_rules_for_invalid_attributes() -> Result<()> {
+        let mut rules_file = NamedTempFile::new()?;
+        write!(rules_file, "objects:\n  Spacer:\n    allowed: [Size]\n")?;
+        let rules = LintRules::load(Some(rules_file.path()))?;
+        assert!(rules.objects.contains_key("Mvr"));
+
+        let doc = Document::parse(
+            "  DisplayGroup:  g\n  GroupNo=  1\n  Spacer:  s\n  Size=  2\n  Color=  \"red\"\n",
+        )?;
+        let issues = lint(&doc, &rules);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].span.start, 5);
+        assert!(issues[0].message.contains("invalid attribute 'Color'"));
+        Ok(())
+    }
+
+    #[test]
+    fn fail_load_rules_with_unknown_field() -> Result<()> {
+        let mut rules_file = NamedTempFile::new()?;
+        write!(rules_file, "objects:\n  Spacer:\n    mandatory: [Size]\n")?;
+        let result = LintRules::load(Some(rules_file.path()));
+        assert!(result.is_err());
+        assert!(format!("{:#}", result.unwrap_err()).contains("unknown field"));
+        Ok(())
+    }
+
+    #[test]
+    fn expression_variables_skip_functions_and_numbers() {
+        assert_eq!(
+            expression_variables("\"max(A, B_2) * 1.5e3 + A\""),
+            vec!["A", "B_2"]
+        );
+    }
+}
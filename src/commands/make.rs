@@ -1,16 +1,24 @@
+use crate::cache::SourceCache;
+use crate::commands::diff::SemanticDiff;
 use crate::commands::drawio::components::extract_components;
 use crate::commands::drawio::to_png::drawio_to_png;
-use crate::config::{Config, Drawio, Filename, RowFiltering, Source};
+use crate::commands::lint::{LintRules, format_issue, lint};
+use crate::config::{
+    Combine, Config, Drawio, Filename, OnCellError, RowFiltering, Sheet, Source, Target,
+};
 use crate::datasource::{
-    CsvSourceReader, DataSourceReader, DataSourceRows, ExcelSourceReader, MultiSourceReader,
+    CsvSourceReader, CtxDataType, DataSourceReader, DataSourceRows, ExcelSourceReader,
+    JsonSourceReader, MultiSourceReader, SPREADSHEET_EXTENSIONS, SourcePart, SqliteSourceReader,
+    YamlSourceReader, set_meta_source,
 };
-use crate::renderer::MiniJinja;
+use crate::renderer::{MiniJinja, Origin, RowContext, error_message};
+use crate::septic::Document;
+use crate::sourcemap::SourceMap;
 use anyhow::{Context, Result, bail};
 use clap::Parser;
 use diffy::{PatchFormatter, create_patch};
 use glob::glob;
 use minijinja::Environment;
-use minijinja::Value;
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::prelude::*;
@@ -27,6 +35,8 @@ enum MakeError {
     CreateOutputFile(anyhow::Error),
     LoadSourceError(anyhow::Error),
     Drawio(anyhow::Error),
+    Lint(anyhow::Error),
+    LintIssuesFound(usize),
     Other(anyhow::Error),
 }
 
@@ -38,11 +48,25 @@ impl std::fmt::Display for MakeError {
                 write!(f, "No change from previous version, exiting.")
             }
             MakeError::MiniJinjaError(err) => {
-                let mut msg = format!("error: {err}");
-                if let Some(err) = err.downcast_ref::<minijinja::Error>()
-                    && err.name().is_some()
+                let row = err.downcast_ref::<RowContext>();
+                let jinja_err = err.downcast_ref::<minijinja::Error>();
+                // Without other context than the row, the message is the error from the template
+                let unwrapped = row.is_some()
+                    || err
+                        .chain()
+                        .next()
+                        .is_some_and(|err| err.is::<minijinja::Error>());
+                let mut msg = match jinja_err {
+                    Some(jinja_err) if unwrapped => format!("error: {}", error_message(jinja_err)),
+                    _ => format!("error: {err}"),
+                };
+                if let Some(jinja_err) = jinja_err
+                    && jinja_err.name().is_some()
                 {
-                    msg = format!("{msg}{}", err.display_debug_info());
+                    msg = format!("{msg}{}", jinja_err.display_debug_info());
+                }
+                if let Some(row) = row {
+                    msg = format!("{msg}\n{row}");
                 }
                 write!(f, "{msg:#}")
             }
@@ -52,6 +76,10 @@ impl std::fmt::Display for MakeError {
             MakeError::CreateOutputFile(e) => write!(f, "Problem creating output file: {e:#}"),
             MakeError::LoadSourceError(e) => write!(f, "{e:#}"),
             MakeError::Drawio(e) => write!(f, "Drawio error: {e:#}"),
+            MakeError::Lint(e) => write!(f, "Lint error: {e:#}"),
+            MakeError::LintIssuesFound(count) => {
+                write!(f, "Lint found {count} issue(s), output was not written.")
+            }
             MakeError::Other(e) => write!(f, "{e:#}"),
         }
     }
@@ -84,15 +112,29 @@ pub struct Make {
     /// Only make if layout or source files have changed since last make
     #[arg(long)]
     pub ifchanged: bool,
+    /// Check the rendered config with the same rules as 'scg lint' before writing it
+    #[arg(long)]
+    pub lint: bool,
+    /// Yaml file with additional lint rules
+    #[arg(long, value_name = "FILE", requires = "lint")]
+    pub lint_rules: Option<PathBuf>,
+    /// Show object-level differences instead of a line patch when verifying content
+    #[arg(long)]
+    pub semantic: bool,
+    /// Write a source map (<outputfile>.map.json) with the template and source row for every output line
+    #[arg(long)]
+    pub sourcemap: bool,
+    /// Read all source files instead of using the parsed rows cached in .scg-cache
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Fail on any use of an undefined variable, not only when it is printed
+    #[arg(long)]
+    pub strict: bool,
 }
 
 impl Make {
     pub fn execute(&self) {
-        let result = cmd_make(
-            &self.config_file,
-            self.ifchanged,
-            &self.var.clone().unwrap_or_default(),
-        );
+        let result = cmd_make(self);
 
         match result {
             Ok(_) => (),
@@ -104,8 +146,8 @@ impl Make {
     }
 }
 
-fn cmd_make(cfg_file: &Path, only_if_changed: bool, globals: &[String]) -> Result<(), MakeError> {
-    let mut cfg_file = cfg_file.to_path_buf();
+fn cmd_make(args: &Make) -> Result<(), MakeError> {
+    let mut cfg_file = args.config_file.clone();
     cfg_file
         .extension()
         .is_none()
@@ -121,18 +163,26 @@ fn cmd_make(cfg_file: &Path, only_if_changed: bool, globals: &[String]) -> Resul
         .with_context(|| format!("'{}'", cfg_file.display()))
         .map_err(MakeError::CfgFileReadError)?;
 
-    if let Some(output) = &cfg.outputfile
-        && only_if_changed
-    {
-        let outfile = relative_root.join(output);
-        if outfile.exists() {
-            let file_list = collect_file_list(&cfg, &cfg_file, &relative_root)
-                .map_err(MakeError::CollectFileList)?;
-            if !timestamps_newer_than(&file_list, &outfile).map_err(MakeError::TimeStampError)? {
-                return Err(MakeError::NoFilesChanged);
+    let targets = cfg.targets();
+    // Whether each target needs to be written. Up-to-date targets are still rendered, so that
+    // the counters they advance give later targets the same values as a full run.
+    let mut changed = vec![true; targets.len()];
+    if args.ifchanged {
+        let file_list = collect_file_list(&cfg, &cfg_file, &relative_root)
+            .map_err(MakeError::CollectFileList)?;
+        for (target, changed) in targets.iter().zip(changed.iter_mut()) {
+            if let Some(output) = target.outputfile {
+                let outfile = relative_root.join(output);
+                *changed = !outfile.exists()
+                    || timestamps_newer_than(&file_list, &outfile)
+                        .map_err(MakeError::TimeStampError)?;
             }
         }
+        if !changed.contains(&true) {
+            return Err(MakeError::NoFilesChanged);
+        }
     }
+    let changed_count = changed.iter().filter(|&&changed| changed).count();
 
     // drawios_*() contain checks on whether .drawio files are newer than output files
     if let Some(drawio) = &cfg.drawio {
@@ -141,57 +191,157 @@ fn cmd_make(cfg_file: &Path, only_if_changed: bool, globals: &[String]) -> Resul
     }
 
     // drawio needs to be done before the templates are rendered, so that the .csv files are available
-    let mut renderer = MiniJinja::new(globals).map_err(MakeError::MiniJinjaError)?;
+    let globals = args.var.clone().unwrap_or_default();
+    let mut renderer = MiniJinja::new(&globals).map_err(MakeError::MiniJinjaError)?;
+    renderer.set_strict(args.strict || cfg.strict);
+    if let Some(macros) = &cfg.macros {
+        renderer
+            .set_macros(&relative_root, &macros.to_vec())
+            .map_err(MakeError::MiniJinjaError)?;
+    }
 
-    let template_path = relative_root.join(&cfg.templatepath);
     renderer
         .set_counters(&cfg.counters)
         .map_err(MakeError::MiniJinjaError)?;
-    renderer
-        .set_loader(&template_path, &cfg.encoding)
-        .map_err(MakeError::MiniJinjaError)?;
 
-    let sources = &cfg.sources.unwrap_or_default();
+    let sources = cfg.sources.as_deref().unwrap_or_default();
+    let cache = (!args.no_cache).then(|| SourceCache::new(&relative_root));
     let all_source_data: HashMap<String, DataSourceRows> =
-        load_all_source_data(sources, &relative_root, &renderer.env)
+        load_all_source_data(sources, &relative_root, &renderer.env, cache.as_ref())
             .map_err(MakeError::LoadSourceError)?;
 
-    for (key, source_data) in all_source_data.iter() {
-        renderer.env.add_global(
-            key,
-            Value::from_serialize(source_data.values().collect::<Vec<_>>()),
-        );
+    renderer.set_sources(&all_source_data);
+
+    let template_paths: Vec<PathBuf> = cfg
+        .templatepath
+        .to_vec()
+        .into_iter()
+        .map(|path| relative_root.join(path))
+        .collect();
+    let mut unchanged = 0;
+    for (target, changed) in targets.iter().zip(changed) {
+        // Each target gets its own copy of the environment, while counters are shared
+        let mut target_renderer = match target.globals {
+            Some(target_globals) => renderer.with_config_globals(target_globals),
+            None => renderer.clone(),
+        };
+        target_renderer
+            .set_loader(&template_paths, target.encoding)
+            .map_err(MakeError::MiniJinjaError)?;
+
+        if !changed {
+            render_target(
+                cfg.adjustspacing,
+                target,
+                &target_renderer,
+                &all_source_data,
+            )?;
+            continue;
+        }
+        match make_target(
+            args,
+            &cfg,
+            target,
+            &target_renderer,
+            &all_source_data,
+            &relative_root,
+        ) {
+            Err(MakeError::NoChangeFromPrevious) => {
+                if changed_count > 1 {
+                    println!(
+                        "No change from previous version of '{}'.",
+                        target.outputfile.unwrap_or_default()
+                    );
+                }
+                unchanged += 1;
+            }
+            result => result?,
+        }
     }
+    if unchanged == changed_count {
+        return Err(MakeError::NoChangeFromPrevious);
+    }
+    Ok(())
+}
 
+/// Render the layout of a target, with the origin of each part of the output
+fn render_target(
+    adjust_spacing: bool,
+    target: &Target,
+    renderer: &MiniJinja,
+    all_source_data: &HashMap<String, DataSourceRows>,
+) -> Result<(String, Vec<Origin>), MakeError> {
     let mut rendered = String::new();
-
-    for template in &cfg.layout {
-        rendered +=
-            &MiniJinja::render_template(&renderer, template, &all_source_data, cfg.adjustspacing)
-                .map_err(MakeError::MiniJinjaError)?
+    let mut origins = Vec::new();
+
+    for template in target.layout {
+        let (template_rendered, template_origins) = renderer
+            .render_template_with_origins(template, all_source_data, adjust_spacing)
+            .map_err(MakeError::MiniJinjaError)?;
+        origins.extend(template_origins.into_iter().map(|origin| Origin {
+            offset: origin.offset + rendered.len(),
+            ..origin
+        }));
+        rendered += &template_rendered;
     }
-    if cfg.adjustspacing {
+    if adjust_spacing {
         rendered = rendered.trim_end().to_string();
         rendered.push('\n');
     }
+    Ok((rendered, origins))
+}
 
-    if let Some(path) = cfg.outputfile.as_ref().map(|f| relative_root.join(f)) {
+fn make_target(
+    args: &Make,
+    cfg: &Config,
+    target: &Target,
+    renderer: &MiniJinja,
+    all_source_data: &HashMap<String, DataSourceRows>,
+    relative_root: &Path,
+) -> Result<(), MakeError> {
+    let (rendered, origins) = render_target(cfg.adjustspacing, target, renderer, all_source_data)?;
+
+    let source_map = SourceMap::new(&rendered, &origins);
+
+    if args.lint {
+        let file_name = target.outputfile.unwrap_or("<stdout>");
+        lint_rendered(
+            &rendered,
+            &source_map,
+            file_name,
+            args.lint_rules.as_deref(),
+        )?;
+    }
+
+    if let Some(path) = target.outputfile.map(|f| relative_root.join(f)) {
+        // Written before the overwrite check, which stops here if the output is unchanged
+        if args.sourcemap {
+            source_map
+                .write(&SourceMap::path_for(&path))
+                .map_err(MakeError::CreateOutputFile)?;
+        }
         if !path.exists()
-            || check_if_overwrite_outfile(&path, &cfg.encoding, &rendered, cfg.verifycontent)?
+            || check_if_overwrite_outfile(
+                &path,
+                target.encoding,
+                &rendered,
+                cfg.verifycontent,
+                args.semantic,
+            )?
         {
             backup_file_if_exists(&path);
             let mut f = fs::File::create(&path)
                 .with_context(|| format!("Problem creating output file '{}'", &path.display()))
                 .map_err(MakeError::CreateOutputFile)?;
 
-            let encoding = encoding_rs::Encoding::for_label(cfg.encoding.as_bytes())
+            let encoding = encoding_rs::Encoding::for_label(target.encoding.as_bytes())
                 .expect("cmd_make: Unknown encoding");
 
             let (buffer, _encoding, _b) = encoding.encode(&rendered);
 
             f.write_all(&buffer)
                 .with_context(|| format!("Problem writing output file '{}'", &path.display()))
-                .map_err(MakeError::CreateOutputFile)?
+                .map_err(MakeError::CreateOutputFile)?;
         }
     } else {
         // TODO: || with input argument for writing to stdout
@@ -200,6 +350,28 @@ fn cmd_make(cfg_file: &Path, only_if_changed: bool, globals: &[String]) -> Resul
     Ok(())
 }
 
+fn lint_rendered(
+    rendered: &str,
+    source_map: &SourceMap,
+    file_name: &str,
+    lint_rules: Option<&Path>,
+) -> Result<(), MakeError> {
+    let rules = LintRules::load(lint_rules).map_err(MakeError::Lint)?;
+    let doc = Document::parse(rendered)
+        .with_context(|| format!("Problem parsing rendered config '{file_name}'"))
+        .map_err(MakeError::Lint)?;
+    let issues = lint(&doc, &rules);
+    for issue in &issues {
+        let origin = source_map.entry_for_line(issue.span.start);
+        eprintln!("{}", format_issue(file_name, issue, origin));
+    }
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(MakeError::LintIssuesFound(issues.len()))
+    }
+}
+
 fn drawios_to_pngs(relative_root: &Path, drawio: &Vec<Drawio>) -> Result<(), MakeError> {
     for item in drawio {
         let input = relative_root.join(&item.input);
@@ -246,6 +418,7 @@ fn check_if_overwrite_outfile(
     encoding: &str,
     rendered: &str,
     verifycontent: bool,
+    semantic: bool,
 ) -> Result<bool, MakeError> {
     let file = fs::File::open(path)
         .with_context(|| format!("Problem opening file '{}'", &path.display()))
@@ -267,11 +440,31 @@ fn check_if_overwrite_outfile(
         return Err(MakeError::NoChangeFromPrevious);
     }
     if verifycontent {
-        let formatted_diff = PatchFormatter::new()
-            .with_color()
-            .fmt_patch(&diff)
-            .to_string();
-        println!("{formatted_diff}");
+        let semantic_diff = semantic.then(|| {
+            Ok::<_, anyhow::Error>(SemanticDiff::new(
+                &Document::parse(&old_file_content)?,
+                &Document::parse(rendered)?,
+            ))
+        });
+        match semantic_diff {
+            Some(Ok(semantic_diff)) => {
+                if semantic_diff.is_empty() {
+                    println!("No semantic differences, only formatting has changed.");
+                } else {
+                    println!("{semantic_diff}");
+                }
+            }
+            _ => {
+                if let Some(Err(err)) = semantic_diff {
+                    eprintln!("Unable to compare semantically, showing line diff: {err:#}");
+                }
+                let formatted_diff = PatchFormatter::new()
+                    .with_color()
+                    .fmt_patch(&diff)
+                    .to_string();
+                println!("{formatted_diff}");
+            }
+        }
         Ok(ask_should_overwrite()
             .context("Unable to read user input")
             .map_err(MakeError::Other)?)
@@ -292,49 +485,142 @@ fn load_all_source_data(
     sources: &[Source],
     relative_root: &Path,
     env: &Environment,
+    cache: Option<&SourceCache>,
 ) -> Result<HashMap<String, DataSourceRows>> {
+    let mut loaded = HashMap::new();
+    for source in sources {
+        let mut source_data = match cache {
+            Some(cache) => load_cached_source_data(source, relative_root, cache)?,
+            None => load_source_data(source, relative_root)?,
+        };
+        set_meta_source(&mut source_data, &source.id);
+        source
+            .apply_computed(&mut source_data, env)
+            .with_context(|| format!("Problem computing columns for source '{}'", source.id))?;
+        loaded.insert(source.id.clone(), source_data);
+    }
+
+    // Joins use the other sources as read, so that the order of the sources does not matter
     sources
         .iter()
         .map(|source| {
-            let source_data = load_source_data(source, relative_root)?;
+            let mut source_data = loaded[&source.id].clone();
+            source
+                .apply_joins(&mut source_data, &loaded)
+                .with_context(|| format!("Problem joining source '{}'", source.id))?;
             let filtered_source_data = source.apply_filters(&source_data, env)?;
             Ok((source.id.clone(), filtered_source_data))
         })
         .collect()
 }
 
+/// Use the cached rows if the source files and options are unchanged, otherwise read the source
+/// and cache the rows. Cached rows with error cells are read again when the source warns about
+/// error cells, so that the warnings are shown.
+fn load_cached_source_data(
+    source: &Source,
+    relative_root: &Path,
+    cache: &SourceCache,
+) -> Result<DataSourceRows> {
+    // Missing files are reported by the reader
+    let Ok(key) = SourceCache::key(source, relative_root) else {
+        return load_source_data(source, relative_root);
+    };
+    let warns = source.on_cell_error.unwrap_or_default() == OnCellError::Warn;
+    if let Some(source_data) = cache.load(&source.id, &key)
+        && !(warns && has_error_cells(&source_data))
+    {
+        return Ok(source_data);
+    }
+    let source_data = load_source_data(source, relative_root)?;
+    if let Err(err) = cache.store(&source.id, &key, &source_data) {
+        eprintln!("Warning: {err:#}");
+    }
+    Ok(source_data)
+}
+
+fn has_error_cells(source_data: &DataSourceRows) -> bool {
+    source_data.values().any(|row| {
+        row.values()
+            .any(|value| matches!(value, CtxDataType::Error(_)))
+    })
+}
+
 fn load_source_data(source: &Source, relative_root: &Path) -> Result<DataSourceRows> {
-    let reader: Box<dyn DataSourceReader> = match &source.filename {
-        Filename::Single(filename) => match Path::new(filename).extension() {
-            Some(ext) if ext == "xlsx" => Box::new(ExcelSourceReader::new(
-                filename,
-                relative_root,
-                source.sheet.as_deref(),
-            )),
-            Some(ext) if ext == "csv" => {
+    let mut parts = source_parts(source, relative_root)
+        .with_context(|| format!("Problem reading source '{}'", source.id))?;
+    let reader: Box<dyn DataSourceReader> = if parts.len() == 1 && source.combine == Combine::Merge
+    {
+        parts.remove(0).reader
+    } else {
+        Box::new(MultiSourceReader::with_parts(parts, source.combine))
+    };
+    reader
+        .read()
+        .with_context(|| format!("Problem reading source '{}'", source.id))
+}
+
+/// One reader per file in the source, or per sheet for Excel files
+fn source_parts(source: &Source, relative_root: &Path) -> Result<Vec<SourcePart>> {
+    let mut parts = Vec::new();
+    for filename in source.filename.to_vec() {
+        let extension = Path::new(filename).extension().and_then(|ext| ext.to_str());
+        let reader: Box<dyn DataSourceReader> = match extension {
+            // Without a sheet, the sheet is given by a named range
+            Some(ext) if SPREADSHEET_EXTENSIONS.contains(&ext) && source.sheet.is_none() => {
+                Box::new(
+                    ExcelSourceReader::new(filename, relative_root, None)
+                        .with_layout(source.table_layout())
+                        .with_on_cell_error(source.on_cell_error.unwrap_or_default()),
+                )
+            }
+            Some(ext) if SPREADSHEET_EXTENSIONS.contains(&ext) => {
+                let patterns = source.sheet.as_ref().map(Sheet::to_vec).unwrap_or_default();
+                for sheet in ExcelSourceReader::matching_sheets(filename, relative_root, &patterns)?
+                {
+                    parts.push(SourcePart {
+                        name: format!("{filename}[{sheet}]"),
+                        reader: Box::new(
+                            ExcelSourceReader::new(filename, relative_root, Some(&sheet))
+                                .with_layout(source.table_layout())
+                                .with_on_cell_error(source.on_cell_error.unwrap_or_default()),
+                        ),
+                        sheet: Some(sheet),
+                    });
+                }
+                continue;
+            }
+            Some("csv") => {
                 let delimiter = source.delimiter.unwrap_or(';');
 
-                Box::new(CsvSourceReader::new(
+                Box::new(
+                    CsvSourceReader::new(filename, relative_root, Some(delimiter))
+                        .with_layout(source.table_layout()),
+                )
+            }
+            Some("json") => Box::new(
+                JsonSourceReader::new(filename, relative_root).with_layout(source.table_layout()),
+            ),
+            Some("yaml" | "yml") => Box::new(
+                YamlSourceReader::new(filename, relative_root).with_layout(source.table_layout()),
+            ),
+            Some("db" | "sqlite") => Box::new(
+                SqliteSourceReader::new(
                     filename,
                     relative_root,
-                    Some(delimiter),
-                ))
-            }
+                    source.query.as_deref().unwrap_or_default(),
+                )
+                .with_layout(source.table_layout()),
+            ),
             _ => bail!("Unsupported file extension for source file '{}'", filename),
-        },
-        Filename::Multiple(filenames) => {
-            let delimiter = source.delimiter.unwrap_or(';');
-
-            Box::new(MultiSourceReader::new(
-                filenames.iter().map(|f| f.as_str()).collect(),
-                relative_root,
-                Some(delimiter),
-            ))
-        }
-    };
-    reader
-        .read()
-        .with_context(|| format!("Problem reading source '{}'", source.id))
+        };
+        parts.push(SourcePart {
+            name: filename.to_string(),
+            sheet: None,
+            reader,
+        });
+    }
+    Ok(parts)
 }
 
 fn collect_file_list(
@@ -344,15 +630,25 @@ fn collect_file_list(
 ) -> Result<HashSet<PathBuf>> {
     let mut files = HashSet::new();
 
-    // The yaml file
+    // The yaml file and all files it extends or includes
     files.insert(cfg_file.to_path_buf());
+    files.extend(config.files.iter().cloned());
+
+    // All files in the template directories
+    for template_path in config.templatepath.to_vec() {
+        let template_root = relative_root.join(Path::new(template_path));
+        for entry in glob(&format!("{}/**/*", template_root.display()))? {
+            let path = entry?;
+            if path.is_file() {
+                files.insert(path);
+            }
+        }
+    }
 
-    // All files in templatedir
-    let template_root = relative_root.join(Path::new(&config.templatepath));
-    for entry in glob(&format!("{}/**/*", template_root.display()))? {
-        let path = entry?;
-        if path.is_file() {
-            files.insert(path);
+    // All macro files
+    if let Some(macros) = &config.macros {
+        for filename in macros.to_vec() {
+            files.insert(relative_root.join(filename));
         }
     }
 
@@ -413,8 +709,11 @@ fn backup_file_if_exists(path: &PathBuf) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{self, Include, IncludeConditional, RowFiltering};
-    use crate::datasource::{DataSourceReader, DataSourceRows, ExcelSourceReader};
+    use crate::config::{
+        self, Include, IncludeConditional, OnCellError, RowFiltering, TemplatePath,
+    };
+    use crate::datasource::{CtxDataType, DataSourceRows};
+    use minijinja::Value;
     use std::fs::File;
     use tempfile::tempdir;
 
@@ -423,28 +722,18 @@ mod tests {
         let source_main = config::Source {
             filename: Filename::Single("test.xlsx".to_string()),
             id: "main".to_string(),
-            sheet: Some("Normals".to_string()),
+            sheet: Some("Normals".into()),
             ..Default::default()
         };
         let source_errors = config::Source {
             filename: "test.xlsx".into(),
             id: "errors".to_string(),
-            sheet: Some("Specials".to_string()),
+            sheet: Some("Specials".into()),
+            on_cell_error: Some(OnCellError::Warn),
             ..Default::default()
         };
         for source in [source_main, source_errors] {
-            let filename = match &source.filename {
-                Filename::Single(filename) => filename,
-                _ => panic!("Expected a single filename, but got multiple filenames"),
-            };
-
-            let reader = ExcelSourceReader::new(
-                filename,
-                Path::new("tests/testdata/"),
-                source.sheet.as_deref(),
-            );
-
-            let source_data = reader.read()?;
+            let source_data = load_source_data(&source, Path::new("tests/testdata/"))?;
             all_source_data.insert(source.id.to_string(), source_data);
         }
         Ok(all_source_data)
@@ -543,10 +832,89 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn load_sheet_pattern_concatenates_rows() -> Result<()> {
+        let source = Source {
+            filename: "wells.xlsx".into(),
+            id: "wells".to_string(),
+            sheet: Some("Wells_*".into()),
+            combine: Combine::Concat,
+            ..Default::default()
+        };
+        let source_data = load_source_data(&source, Path::new("tests/testdata/"))?;
+        assert_eq!(source_data.keys().collect::<Vec<_>>(), ["N1", "N2", "S1"]);
+        assert_eq!(
+            source_data["S1"]["_sheet"],
+            CtxDataType::String("Wells_South".to_string())
+        );
+        assert_eq!(source_data["S1"]["depth"], CtxDataType::Int(300));
+        Ok(())
+    }
+
+    #[test]
+    fn load_sheet_list_merges_columns() -> Result<()> {
+        let source = Source {
+            filename: "wells.xlsx".into(),
+            id: "wells".to_string(),
+            sheet: Some(config::Sheet::Multiple(vec![
+                "Wells_North".to_string(),
+                "Tuning".to_string(),
+            ])),
+            ..Default::default()
+        };
+        let source_data = load_source_data(&source, Path::new("tests/testdata/"))?;
+        assert_eq!(source_data.keys().collect::<Vec<_>>(), ["N1", "N2"]);
+        assert_eq!(source_data["N2"]["depth"], CtxDataType::Int(200));
+        assert_eq!(source_data["N2"]["gain"], CtxDataType::Float(2.5));
+        assert!(!source_data["N2"].contains_key("_sheet"));
+        Ok(())
+    }
+
+    #[test]
+    fn load_combines_xlsx_and_csv() -> Result<()> {
+        let dir = tempdir()?;
+        fs::copy("tests/testdata/wells.xlsx", dir.path().join("wells.xlsx"))?;
+        fs::write(
+            dir.path().join("extra.csv"),
+            "well;area\nN1;north\nN2;north\n",
+        )?;
+        let source = Source {
+            filename: vec!["wells.xlsx", "extra.csv"].into(),
+            id: "wells".to_string(),
+            sheet: Some("Wells_North".into()),
+            ..Default::default()
+        };
+        let source_data = load_source_data(&source, dir.path())?;
+        assert_eq!(
+            source_data["N1"]["area"],
+            CtxDataType::String("north".to_string())
+        );
+        assert_eq!(source_data["N1"]["depth"], CtxDataType::Int(100));
+        Ok(())
+    }
+
+    #[test]
+    fn load_fails_on_unmatched_sheet_pattern() {
+        let source = Source {
+            filename: "wells.xlsx".into(),
+            id: "wells".to_string(),
+            sheet: Some("Risers_*".into()),
+            ..Default::default()
+        };
+        let result = load_source_data(&source, Path::new("tests/testdata/"));
+        assert!(
+            format!("{:#}", result.unwrap_err())
+                .contains("No sheet matching 'Risers_*' in 'wells.xlsx'")
+        );
+    }
+
     #[test]
     fn render_with_normal_values() -> Result<()> {
         let mut renderer = MiniJinja::new(&[])?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
 
         let template = config::Template {
             name: "01_normals.tmpl".to_string(),
@@ -568,7 +936,10 @@ mod tests {
     #[test]
     fn render_with_special_values() -> Result<()> {
         let mut renderer = MiniJinja::new(&[])?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
         let template = config::Template {
             name: "02_specials.tmpl".to_string(),
             source: Some("errors".to_string()),
@@ -590,7 +961,10 @@ mod tests {
     fn render_with_global_variables() -> Result<()> {
         let globals = ["glob".to_string(), "globvalue".to_string()];
         let mut renderer = MiniJinja::new(&globals)?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
         let template = config::Template {
             name: "03_globals.tmpl".to_string(),
             ..Default::default()
@@ -606,18 +980,16 @@ mod tests {
     // FIXME: Horrible test with too much code duplication from cmd_main()
     fn render_with_global_source_no_iteration() -> Result<()> {
         let mut renderer = MiniJinja::new(&[])?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
         let template = config::Template {
             name: "08_sources.tmpl".to_string(),
             ..Default::default()
         };
         let all_source_data = get_all_source_data()?;
-        for (key, source_data) in all_source_data.iter() {
-            renderer.env.add_global(
-                key,
-                Value::from_serialize(source_data.values().collect::<Vec<_>>()),
-            );
-        }
+        renderer.set_sources(&all_source_data);
         let result = MiniJinja::render_template(&renderer, &template, &HashMap::new(), true)?
             .trim()
             .replace('\r', "");
@@ -632,7 +1004,10 @@ mod tests {
     // FIXME: Horrible test with too much code duplication from cmd_main()
     fn render_with_global_source_and_iteration() -> Result<()> {
         let mut renderer = MiniJinja::new(&[])?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
         let template = config::Template {
             name: "08_sources.tmpl".to_string(),
             source: Some("main".to_string()),
@@ -640,12 +1015,7 @@ mod tests {
             ..Default::default()
         };
         let all_source_data = get_all_source_data()?;
-        for (key, source_data) in all_source_data.iter() {
-            renderer.env.add_global(
-                key,
-                Value::from_serialize(source_data.values().collect::<Vec<_>>()),
-            );
-        }
+        renderer.set_sources(&all_source_data);
         let result = MiniJinja::render_template(&renderer, &template, &all_source_data, true)?
             .trim()
             .replace('\r', "");
@@ -659,7 +1029,10 @@ mod tests {
     #[test]
     fn render_uses_latin1_encoding() -> Result<()> {
         let mut renderer = MiniJinja::new(&[])?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
         let template = config::Template {
             name: "06_encoding.tmpl".to_string(),
             ..Default::default()
@@ -674,7 +1047,10 @@ mod tests {
     #[test]
     fn render_adjusts_spacing() -> Result<()> {
         let mut renderer = MiniJinja::new(&[])?;
-        renderer.set_loader(Path::new("tests/testdata/templates/"), "Windows-1252")?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
         let template = config::Template {
             name: "00_plaintext.tmpl".to_string(),
             ..Default::default()
@@ -690,6 +1066,509 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn render_records_origin_per_row() -> Result<()> {
+        let mut renderer = MiniJinja::new(&[])?;
+        renderer.set_loader(
+            &[PathBuf::from("tests/testdata/templates/")],
+            "Windows-1252",
+        )?;
+        let template = config::Template {
+            name: "01_normals.tmpl".to_string(),
+            source: Some("main".to_string()),
+            ..Default::default()
+        };
+        let all_source_data = get_all_source_data()?;
+        let (rendered, origins) =
+            renderer.render_template_with_origins(&template, &all_source_data, true)?;
+        let rows: Vec<_> = origins.iter().map(|o| o.row.as_deref().unwrap()).collect();
+        assert_eq!(rows, ["one", "two", "three"]);
+        assert!(rendered[origins[1].offset..].starts_with("String: two"));
+        Ok(())
+    }
+
+    #[test]
+    fn lint_rendered_fails_on_issues() {
+        let rendered = "  SmpcAppl:  appl\r\n  CalcPvr:  C\r\n  Alg=  \"A\"\r\n";
+        let origins = [Origin {
+            template: "calc.tmpl".to_string(),
+            source: None,
+            row: None,
+            offset: 0,
+        }];
+        let source_map = SourceMap::new(rendered, &origins);
+        let result = lint_rendered(rendered, &source_map, "out.cnfg", None);
+        assert!(matches!(result, Err(MakeError::LintIssuesFound(1))));
+        let result = lint_rendered("  SmpcAppl:  appl\r\n", &source_map, "out.cnfg", None);
+        assert!(result.is_ok());
+    }
+
+    fn make_args(config_file: &Path) -> Make {
+        Make {
+            config_file: config_file.to_path_buf(),
+            var: None,
+            ifchanged: false,
+            lint: false,
+            lint_rules: None,
+            semantic: false,
+            sourcemap: false,
+            no_cache: false,
+            strict: false,
+        }
+    }
+
+    #[test]
+    fn make_provides_row_meta_and_reports_failing_row() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            "% {{ _meta.source }} from {{ _meta.file }} row {{ _meta.row }} key {{ _meta.key }}",
+        )?;
+        fs::write(
+            dir.path().join("templates/fail.tmpl"),
+            "{{ depth | bitmask(depth) }}",
+        )?;
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;3\nN2;-1\n")?;
+        let cfg_file = dir.path().join("config.yaml");
+        let config = |template: &str| {
+            format!(
+                r#"
+outputfile: main.cnfg
+templatepath: templates
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: {template}
+    source: wells
+"#
+            )
+        };
+        fs::write(&cfg_file, config("main.tmpl"))?;
+        let mut args = make_args(&cfg_file);
+        args.no_cache = true;
+        assert!(cmd_make(&args).is_ok());
+        let rendered = fs::read_to_string(dir.path().join("main.cnfg"))?;
+        assert!(rendered.contains("% wells from wells.csv row 3 key N2"));
+
+        fs::write(&cfg_file, config("fail.tmpl"))?;
+        let message = cmd_make(&args).unwrap_err().to_string();
+        assert!(message.starts_with("error: invalid operation"), "{message}");
+        assert!(
+            message.ends_with("while rendering row 'N2' in source 'wells' (wells.csv, row 3)"),
+            "{message}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn make_strict_reports_undefined_expression_and_row() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            "{{ well }}\n{% if unit %}{{ unit }}{% endif %}",
+        )?;
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;3\n")?;
+        let cfg_file = dir.path().join("config.yaml");
+        let config = |strict: bool| {
+            format!(
+                r#"
+outputfile: main.cnfg
+templatepath: templates
+strict: {strict}
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: main.tmpl
+    source: wells
+"#
+            )
+        };
+        fs::write(&cfg_file, config(false))?;
+        let mut args = make_args(&cfg_file);
+        args.no_cache = true;
+        assert!(cmd_make(&args).is_ok());
+
+        args.strict = true;
+        let message = cmd_make(&args).unwrap_err().to_string();
+        assert!(
+            message.starts_with("error: undefined value: 'unit' (in main.tmpl:2)"),
+            "{message}"
+        );
+        assert!(
+            message.ends_with("while rendering row 'N1' in source 'wells' (wells.csv, row 2)"),
+            "{message}"
+        );
+
+        args.strict = false;
+        fs::write(&cfg_file, config(true))?;
+        assert!(cmd_make(&args).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn make_imports_macros_into_every_template() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::create_dir(dir.path().join("macros"))?;
+        fs::write(
+            dir.path().join("macros/mvr.jinja"),
+            "{% macro tag(name) %}Mvr: {{ name }}{% endmacro %}",
+        )?;
+        fs::write(
+            dir.path().join("macros/display.jinja"),
+            "{% macro text(value) %}Text1= {{ value | septic_str }}{% endmacro %}",
+        )?;
+        fs::write(
+            dir.path().join("templates/base.tmpl"),
+            "{% block body %}{% endblock %}\n{% include 'footer.tmpl' %}",
+        )?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            "{% extends 'base.tmpl' %}{% block body %}{{ mvr.tag(well) }}{% endblock %}",
+        )?;
+        fs::write(
+            dir.path().join("templates/footer.tmpl"),
+            "{{ display.text('end') }}",
+        )?;
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;3\n")?;
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath: templates
+verifycontent: false
+macros:
+  - macros/mvr.jinja
+  - macros/display.jinja
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: main.tmpl
+    source: wells
+"#,
+        )?;
+        let mut args = make_args(&cfg_file);
+        args.no_cache = true;
+        cmd_make(&args)?;
+        let rendered = fs::read_to_string(dir.path().join("main.cnfg"))?;
+        assert_eq!(rendered.trim_end(), "Mvr: N1\nText1= \"end\"");
+        Ok(())
+    }
+
+    #[test]
+    fn make_searches_template_paths_in_order() -> Result<()> {
+        let dir = tempdir()?;
+        let files = [
+            (
+                "shared/base.tmpl",
+                "{% include 'header.tmpl' %}\n{% block body %}shared body{% endblock %}\n{% include 'footer.tmpl' %}",
+            ),
+            ("shared/header.tmpl", "shared header"),
+            ("shared/footer.tmpl", "shared footer"),
+            ("asset/header.tmpl", "asset header"),
+            (
+                "asset/main.tmpl",
+                "{% extends 'base.tmpl' %}{% block body %}asset body {{ well }}{% endblock %}",
+            ),
+            ("wells.csv", "well;depth\nN1;3\n"),
+        ];
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, content)?;
+        }
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath:
+  - asset
+  - shared
+verifycontent: false
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: main.tmpl
+    source: wells
+"#,
+        )?;
+        let mut args = make_args(&cfg_file);
+        args.no_cache = true;
+        cmd_make(&args)?;
+        let rendered = fs::read_to_string(dir.path().join("main.cnfg"))?;
+        assert_eq!(
+            rendered.trim_end(),
+            "asset header\nasset body N1\nshared footer"
+        );
+
+        let cfg = Config::new(&cfg_file)?;
+        let file_list = collect_file_list(&cfg, &cfg_file, dir.path())?;
+        assert!(file_list.contains(&dir.path().join("asset").join("header.tmpl")));
+        assert!(file_list.contains(&dir.path().join("shared").join("header.tmpl")));
+        Ok(())
+    }
+
+    #[test]
+    fn make_reports_template_invalid_in_encoding() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            b"Well\n\xd8stre {{ well }}\n",
+        )?;
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;3\n")?;
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath: templates
+encoding: utf-8
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: main.tmpl
+    source: wells
+"#,
+        )?;
+        let mut args = make_args(&cfg_file);
+        args.no_cache = true;
+        let message = cmd_make(&args).unwrap_err().to_string();
+        assert!(message.contains("cannot read template file '"), "{message}");
+        assert!(
+            message.contains("main.tmpl': not valid UTF-8 (line 2)"),
+            "{message}"
+        );
+        assert!(!dir.path().join("main.cnfg").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn make_caches_source_rows() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            "{{ well }}={{ depth }};",
+        )?;
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;100\nN2;2.5\n")?;
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath: templates
+verifycontent: false
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: main.tmpl
+    source: wells
+"#,
+        )?;
+        let mut args = make_args(&cfg_file);
+        let cache_file = dir.path().join(".scg-cache/wells.json");
+        assert!(cmd_make(&args).is_ok());
+        assert!(cache_file.exists());
+        let first = fs::read_to_string(dir.path().join("main.cnfg"))?;
+        assert!(first.contains("N1=100;") && first.contains("N2=2.5;"));
+
+        // Rendering from the cache gives the same output
+        assert!(matches!(
+            cmd_make(&args),
+            Err(MakeError::NoChangeFromPrevious)
+        ));
+
+        // The rows come from the cache while the key matches, shown by a marker in the entry
+        let cached = fs::read_to_string(&cache_file)?;
+        assert!(cached.contains(r#"{"Int":100}"#), "{cached}");
+        fs::write(
+            &cache_file,
+            cached.replace(r#"{"Int":100}"#, r#"{"Int":777}"#),
+        )?;
+        assert!(cmd_make(&args).is_ok());
+        let marked = fs::read_to_string(dir.path().join("main.cnfg"))?;
+        assert!(marked.contains("N1=777;"), "{marked}");
+
+        // A changed file is read again
+        fs::write(dir.path().join("wells.csv"), "well;depth\nN1;300\n")?;
+        assert!(cmd_make(&args).is_ok());
+        let second = fs::read_to_string(dir.path().join("main.cnfg"))?;
+        assert!(second.contains("N1=300;") && !second.contains("N2"));
+
+        fs::remove_dir_all(dir.path().join(".scg-cache"))?;
+        args.no_cache = true;
+        assert!(matches!(
+            cmd_make(&args),
+            Err(MakeError::NoChangeFromPrevious)
+        ));
+        assert!(!cache_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn make_writes_sourcemap_when_output_is_unchanged() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(dir.path().join("templates/main.tmpl"), "{{ well }}\n\n")?;
+        fs::write(dir.path().join("wells.csv"), "well\nN1\nN2\n")?;
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath: templates
+sources:
+  - filename: wells.csv
+    id: wells
+layout:
+  - name: main.tmpl
+    source: wells
+"#,
+        )?;
+        let mut args = make_args(&cfg_file);
+        args.no_cache = true;
+        args.sourcemap = true;
+        let map_file = SourceMap::path_for(&dir.path().join("main.cnfg"));
+        assert!(cmd_make(&args).is_ok());
+        assert!(map_file.exists());
+
+        fs::remove_file(&map_file)?;
+        assert!(matches!(
+            cmd_make(&args),
+            Err(MakeError::NoChangeFromPrevious)
+        ));
+        let map = SourceMap::read(&map_file)?;
+        assert_eq!(
+            map.entry_for_line(3).and_then(|entry| entry.row.as_deref()),
+            Some("N2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn make_reads_cached_error_cells_again_to_warn() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            "{{ _meta.key }}\n\n",
+        )?;
+        fs::copy("tests/testdata/test.xlsx", dir.path().join("test.xlsx"))?;
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath: templates
+verifycontent: false
+sources:
+  - filename: test.xlsx
+    id: errors
+    sheet: Specials
+layout:
+  - name: main.tmpl
+    source: errors
+"#,
+        )?;
+        let args = make_args(&cfg_file);
+        assert!(cmd_make(&args).is_ok());
+
+        // The file is read again instead of the cache, which would otherwise render ONE
+        let cache_file = dir.path().join(".scg-cache/errors.json");
+        let cached = fs::read_to_string(&cache_file)?;
+        assert!(cached.contains(r#""Col1":{"String":"one"}"#), "{cached}");
+        fs::write(
+            &cache_file,
+            cached.replace(r#""Col1":{"String":"one"}"#, r#""Col1":{"String":"ONE"}"#),
+        )?;
+        assert!(matches!(
+            cmd_make(&args),
+            Err(MakeError::NoChangeFromPrevious)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn make_renders_all_outputs() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(
+            dir.path().join("templates/main.tmpl"),
+            "{{ name }}: {{ cnt() }}",
+        )?;
+        let cfg_file = dir.path().join("config.yaml");
+        fs::write(
+            &cfg_file,
+            r#"
+outputfile: main.cnfg
+templatepath: templates
+counters:
+  - name: cnt
+layout:
+  - name: main.tmpl
+outputs:
+  - outputfile: second.cnfg
+    encoding: utf-8
+    globals:
+      name: second
+    layout:
+      - name: main.tmpl
+"#,
+        )?;
+        let mut args = make_args(&cfg_file);
+        args.var = Some(vec!["name".to_string(), "first".to_string()]);
+        assert!(cmd_make(&args).is_ok());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("main.cnfg"))?,
+            "first: 1\n"
+        );
+        // Command line variables take precedence over output globals
+        assert_eq!(
+            fs::read_to_string(dir.path().join("second.cnfg"))?,
+            "first: 2\n"
+        );
+
+        args.ifchanged = true;
+        assert!(matches!(cmd_make(&args), Err(MakeError::NoFilesChanged)));
+
+        // Only main.cnfg is older than the inputs and gets rendered again
+        filetime::set_file_mtime(
+            dir.path().join("main.cnfg"),
+            filetime::FileTime::from_unix_time(0, 0),
+        )?;
+        assert!(matches!(
+            cmd_make(&args),
+            Err(MakeError::NoChangeFromPrevious)
+        ));
+
+        // Only second.cnfg is stale. main.cnfg is rendered first without being written, so
+        // second.cnfg gets the same counter value as in a full run.
+        filetime::set_file_mtime(dir.path().join("main.cnfg"), filetime::FileTime::now())?;
+        fs::remove_file(dir.path().join("second.cnfg"))?;
+        let main_modified = fs::metadata(dir.path().join("main.cnfg"))?.modified()?;
+        assert!(cmd_make(&args).is_ok());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("second.cnfg"))?,
+            "first: 2\n"
+        );
+        assert_eq!(
+            fs::metadata(dir.path().join("main.cnfg"))?.modified()?,
+            main_modified
+        );
+        Ok(())
+    }
+
     #[test]
     fn collect_file_list_works() -> Result<()> {
         let sources = Some(vec![
@@ -720,9 +1599,11 @@ mod tests {
         let layout = vec![];
         let cfg = config::Config {
             outputfile: Some("outfile".to_string()),
-            templatepath: String::from(dir.path().to_str().unwrap()),
+            templatepath: TemplatePath::Single(dir.path().to_str().unwrap().to_string()),
+            macros: Some(Filename::Single("macros/mvr.jinja".to_string())),
             sources,
             layout,
+            files: vec![cfg_file.clone(), relative_root.join("base.yaml")],
             ..Default::default()
         };
 
@@ -731,11 +1612,19 @@ mod tests {
         for filename in [file1.to_str(), file2.to_str(), file3.to_str()].iter() {
             expected.insert(PathBuf::from("relative_root/templates").join(filename.unwrap()));
         }
-        for filename in ["source1", "source2", "config.yaml"].iter() {
+        for filename in [
+            "source1",
+            "source2",
+            "config.yaml",
+            "base.yaml",
+            "macros/mvr.jinja",
+        ]
+        .iter()
+        {
             expected.insert(PathBuf::from("relative_root").join(filename));
         }
 
-        assert!(result.len() == 6);
+        assert!(result.len() == 8);
         assert!(result == expected);
         Ok(())
     }
@@ -1,12 +1,17 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use indexmap::IndexMap;
 use minijinja::{Environment, context};
 use schemars::JsonSchema;
-use serde::Deserialize;
-use std::collections::HashSet;
-use std::fs;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-use crate::datasource::DataSourceRows;
+use crate::datasource::{
+    CtxDataType, DataSourceRows, META_COLUMN, SPREADSHEET_EXTENSIONS, TableLayout, coerce,
+    display_value,
+};
+
+mod compose;
 
 const fn _default_true() -> bool {
     true
@@ -25,6 +30,123 @@ pub enum Filename {
     Multiple(Vec<String>),
 }
 
+#[derive(Deserialize, Debug, JsonSchema)]
+#[serde(untagged)]
+pub enum TemplatePath {
+    /// Single template directory as a string
+    Single(String),
+    /// Multiple template directories as a list of strings, searched in the order given
+    Multiple(Vec<String>),
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+#[serde(untagged)]
+pub enum Sheet {
+    /// Single sheet name or pattern as a string
+    Single(String),
+    /// Multiple sheet names or patterns as a list of strings
+    Multiple(Vec<String>),
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine {
+    /// Merge the columns of rows with the same key. All keys in the first file or sheet must exist in the others
+    #[default]
+    Merge,
+    /// Add the rows from each file or sheet after each other. Rows from sheets get a `_sheet` column with the sheet name
+    Concat,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnCellError {
+    /// Stop with an error that lists the sheet, cell and column of every error cell
+    Fail,
+    /// Print a warning for every error cell and keep the error value, like #N/A
+    #[default]
+    Warn,
+    /// Treat error cells as empty cells
+    Empty,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    /// Text. Numbers keep the formatting they have in the file
+    String,
+    /// Whole number
+    Int,
+    /// Decimal number. A decimal comma is accepted
+    Float,
+    /// true/false, yes/no, on/off or 1/0
+    Bool,
+    /// Date or date and time. Text is read with `format`, or as ISO-8601 like 2024-05-31 or 2024-05-31T14:30:00
+    DateTime,
+}
+
+impl std::fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ColumnType::String => "string",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::DateTime => "datetime",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Column {
+    /// The type that all values in the column are converted to
+    #[serde(rename = "type")]
+    pub kind: Option<ColumnType>,
+    /// Value to use for empty cells
+    pub default: Option<serde_json::Value>,
+    /// Whether empty cells are an error, defaults to false
+    #[serde(default)]
+    pub required: bool,
+    /// List of allowed values
+    #[serde(rename = "enum")]
+    pub allowed: Option<Vec<serde_json::Value>>,
+    /// Regular expression that the whole value must match
+    pub pattern: Option<String>,
+    /// strftime format of the text in datetime columns, like %d.%m.%Y. Defaults to ISO-8601
+    pub format: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinType {
+    /// Keep all rows, and add the columns of the matching row if there is one
+    #[default]
+    Left,
+    /// Keep only rows that have a matching row
+    Inner,
+    /// Add a list of all matching rows
+    Many,
+}
+
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Join {
+    /// Id of the source to join with
+    pub source: String,
+    /// Column in this source with the value to match. Defaults to the row key
+    pub on: Option<String>,
+    /// Column in the joined source to match against. Defaults to the row key of the joined source
+    pub to: Option<String>,
+    /// How to join the rows: left, inner or many. Defaults to left
+    #[serde(default, rename = "type")]
+    pub kind: JoinType,
+    /// Column to put the matching row in. Without it, the columns of the matching row are added to the row. For many, defaults to the id of the joined source
+    #[serde(rename = "as")]
+    pub as_: Option<String>,
+}
+
 #[derive(Deserialize, Debug, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Counter {
@@ -82,6 +204,57 @@ pub enum Include {
     Conditional(IncludeConditional),
 }
 
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Smallest value first
+    #[default]
+    Asc,
+    /// Largest value first
+    Desc,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortCompare {
+    /// Text where runs of digits are compared as numbers, so that FI-2 comes before FI-10
+    #[default]
+    Natural,
+    /// Numbers. Values that are not numbers come last
+    Numeric,
+}
+
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SortColumn {
+    /// The column to sort by
+    pub column: String,
+    /// Sort order, asc or desc. Defaults to asc
+    #[serde(default)]
+    pub order: SortOrder,
+    /// How to compare values, natural or numeric. Defaults to natural
+    #[serde(default)]
+    pub compare: SortCompare,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+#[serde(untagged)]
+pub enum SortKey {
+    /// Sort ascending by a column with natural ordering
+    Column(String),
+    /// Sort by a column with the given order and comparison
+    Spec(SortColumn),
+}
+
+impl SortKey {
+    fn spec(&self) -> (&str, SortOrder, SortCompare) {
+        match self {
+            SortKey::Column(column) => (column, SortOrder::default(), SortCompare::default()),
+            SortKey::Spec(spec) => (&spec.column, spec.order, spec.compare),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Source {
@@ -93,10 +266,36 @@ pub struct Source {
     pub exclude: Option<Vec<Include>>,
     /// The unique identifier for this source
     pub id: String,
-    /// Optional sheet name for .xlsx files
-    pub sheet: Option<String>,
+    /// Sheet name(s) for .xlsx, .xlsm, .xls and .ods files. Each name may be a pattern like Wells_*
+    pub sheet: Option<Sheet>,
     /// Optional delimiter for .csv files
     pub delimiter: Option<char>,
+    /// Column(s) to use as row key. A list gives a composite key. Defaults to the first column
+    #[serde(default, deserialize_with = "deserialize_string_or_vec_as_vec")]
+    pub key: Option<Vec<String>>,
+    /// Separator between the values in a composite key, defaults to "_"
+    pub key_separator: Option<String>,
+    /// Row number of the header row in spreadsheet and .csv files, defaults to the first row
+    pub header_row: Option<usize>,
+    /// Row number of the first data row in spreadsheet and .csv files, defaults to the row after the header row
+    pub data_start_row: Option<usize>,
+    /// Cell range like C3:H200 or the name of a named range in spreadsheet files
+    pub range: Option<String>,
+    /// SQL query that selects the rows from .db and .sqlite files. The first column is used as row key
+    pub query: Option<String>,
+    /// How to combine multiple files or sheets, defaults to merge
+    #[serde(default)]
+    pub combine: Combine,
+    /// What to do with error cells like #N/A in spreadsheet files: fail, warn or empty. Defaults to warn
+    pub on_cell_error: Option<OnCellError>,
+    /// Types and constraints for columns, checked for every row when the source is read
+    pub columns: Option<BTreeMap<String, Column>>,
+    /// Columns to add to each row, given as MiniJinja expressions that can use the other columns in the row. Evaluated in order, before include and exclude
+    pub computed: Option<IndexMap<String, String>>,
+    /// Other sources to join rows from, applied after all sources are read and before include and exclude
+    pub join: Option<Vec<Join>>,
+    /// Optional list of columns to sort the rows by, applied after include and exclude
+    pub sort_by: Option<Vec<SortKey>>,
 }
 
 #[derive(Deserialize, Debug, Default, JsonSchema)]
@@ -110,6 +309,10 @@ pub struct Template {
     pub include: Option<Vec<Include>>,
     /// Optional list of fields from source to exclude in iteration
     pub exclude: Option<Vec<Include>>,
+    /// Optional column in source to group rows by. The template is rendered once per distinct value, with `group` and `rows` available
+    pub group_by: Option<String>,
+    /// Optional list of columns to sort the rows by, applied after include and exclude
+    pub sort_by: Option<Vec<SortKey>>,
 }
 
 #[derive(Deserialize, Debug, Default, JsonSchema)]
@@ -122,31 +325,124 @@ pub struct Drawio {
     pub csvoutput: Option<String>,
 }
 
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    /// The file that will be generated. Writes to stdout if not specified.
+    pub outputfile: Option<String>,
+    /// The encoding to use for template files and the outputfile. Defaults to the top-level encoding
+    pub encoding: Option<String>,
+    /// Global variables for this output only. Overridden by variables given on the command line
+    pub globals: Option<BTreeMap<String, serde_json::Value>>,
+    /// List of templates in the order they should be rendered
+    pub layout: Vec<Template>,
+}
+
+/// A file to render, either from the top-level layout or from an entry in outputs
+#[derive(Debug)]
+pub struct Target<'a> {
+    pub outputfile: Option<&'a str>,
+    pub encoding: &'a str,
+    pub globals: Option<&'a BTreeMap<String, serde_json::Value>>,
+    pub layout: &'a [Template],
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ListMerge {
+    /// Add the items after the inherited items. Sources and counters replace inherited ones with the same id or name
+    #[default]
+    Append,
+    /// Replace the inherited items
+    Replace,
+    /// Insert the items before the inherited item with this name
+    Before(String),
+    /// Insert the items after the inherited item with this name
+    After(String),
+}
+
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MergeRules {
+    /// How to merge layout items, identified by name
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    #[schemars(with = "ListMerge")]
+    pub layout: ListMerge,
+    /// How to merge sources, identified by id
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    #[schemars(with = "ListMerge")]
+    pub sources: ListMerge,
+    /// How to merge counters, identified by name
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    #[schemars(with = "ListMerge")]
+    pub counters: ListMerge,
+    /// How to merge drawio items, identified by input
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    #[schemars(with = "ListMerge")]
+    pub drawio: ListMerge,
+    /// How to merge outputs, identified by outputfile
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    #[schemars(with = "ListMerge")]
+    pub outputs: ListMerge,
+}
+
+impl MergeRules {
+    /// The rule for a top-level list, or None if the key is not a list that can be merged
+    fn get(&self, list_key: &str) -> Option<&ListMerge> {
+        match list_key {
+            "layout" => Some(&self.layout),
+            "sources" => Some(&self.sources),
+            "counters" => Some(&self.counters),
+            "drawio" => Some(&self.drawio),
+            "outputs" => Some(&self.outputs),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Default, JsonSchema)]
 #[serde(deny_unknown_fields)]
 #[schemars(title = "Septic Config Generator Configuration")]
 pub struct Config {
+    /// Base config file that this file extends
+    pub extends: Option<String>,
+    /// List of config fragments to merge into this config
+    pub include: Option<Vec<String>>,
+    /// How the lists in this file are merged with lists from extends and include. Defaults to append
+    pub merge: Option<MergeRules>,
     /// The file that will be generated. Writes to stdout if not specified.
     pub outputfile: Option<String>,
     #[serde(default = "_default_encoding")]
     /// The encoding to use for template files and the outputfile. Use any label specified in https://encoding.spec.whatwg.org/#concept-encoding-get
     pub encoding: String,
-    /// The directory that contains all template files
-    pub templatepath: String,
+    /// The directory that contains all template files, or a list of directories to search in order. A template in an earlier directory overrides one with the same name in a later directory
+    pub templatepath: TemplatePath,
     #[serde(default = "_default_true")]
     /// Whether to ensure exactly one newline between rendered template files
     pub adjustspacing: bool,
     #[serde(default = "_default_true")]
     /// Whether to warn about differences from an already existing rendered file
     pub verifycontent: bool,
+    /// Whether any use of an undefined variable is an error, not only printing it. Can also be enabled with --strict
+    #[serde(default)]
+    pub strict: bool,
+    /// Macro file or list of macro files that are imported into every template. The macros in a file are available under the file name without extension, e.g. `mvr.jinja` as `mvr`
+    pub macros: Option<Filename>,
     /// List of global auto-incrementing counters
     pub counters: Option<Vec<Counter>>,
     /// List of source file configurations
     pub sources: Option<Vec<Source>>,
     /// List of templates in the order they should be rendered
+    #[serde(default)]
     pub layout: Vec<Template>,
+    /// List of additional files to render with the same sources and counters
+    pub outputs: Option<Vec<Output>>,
     /// List of .drawio files to process
     pub drawio: Option<Vec<Drawio>>,
+    /// The config file and all files it extends or includes
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub files: Vec<PathBuf>,
 }
 
 pub trait RowFiltering {
@@ -154,6 +450,8 @@ pub trait RowFiltering {
 
     fn get_exclude(&self) -> &Option<Vec<Include>>;
 
+    fn get_sort_by(&self) -> &Option<Vec<SortKey>>;
+
     fn apply_filters(
         &self,
         source_rows: &DataSourceRows,
@@ -178,6 +476,19 @@ pub trait RowFiltering {
             }
         }
 
+        if let Some(sort_by) = self.get_sort_by() {
+            // A typo would otherwise compare every row as empty and silently keep the file order
+            for key in sort_by {
+                let (column, _, _) = key.spec();
+                if !source_rows.is_empty()
+                    && !source_rows.values().any(|row| row.contains_key(column))
+                {
+                    bail!("unknown column '{column}' in sort_by");
+                }
+            }
+            sort_rows(&mut filtered_rows, sort_by);
+        }
+
         Ok(filtered_rows)
     }
 }
@@ -193,6 +504,172 @@ impl Default for Filename {
     }
 }
 
+impl Filename {
+    /// All filenames in the order they were given
+    pub fn to_vec(&self) -> Vec<&str> {
+        match self {
+            Filename::Single(filename) => vec![filename.as_str()],
+            Filename::Multiple(filenames) => filenames.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl Default for TemplatePath {
+    fn default() -> Self {
+        TemplatePath::Single(String::new())
+    }
+}
+
+impl TemplatePath {
+    /// All template directories in the order they are searched
+    pub fn to_vec(&self) -> Vec<&str> {
+        match self {
+            TemplatePath::Single(path) => vec![path.as_str()],
+            TemplatePath::Multiple(paths) => paths.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl Source {
+    /// Where to find the table in the source files and how to identify the rows
+    pub fn table_layout(&self) -> TableLayout {
+        TableLayout {
+            key: self.key.clone().unwrap_or_default(),
+            key_separator: self.key_separator.clone(),
+            header_row: self.header_row,
+            data_start_row: self.data_start_row,
+            range: self.range.clone(),
+            columns: self.columns.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Evaluate the `computed` expressions for each row and add the results as columns
+    pub fn apply_computed(
+        &self,
+        source_rows: &mut DataSourceRows,
+        env: &Environment,
+    ) -> Result<()> {
+        let Some(computed) = &self.computed else {
+            return Ok(());
+        };
+        let expressions = computed
+            .iter()
+            .map(|(column, expr)| {
+                let compiled = env.compile_expression(expr).with_context(|| {
+                    format!("Invalid expression for computed column '{column}'")
+                })?;
+                Ok((column, compiled))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (key, row) in source_rows.iter_mut() {
+            for (column, expr) in &expressions {
+                let value = expr.eval(&*row).with_context(|| {
+                    format!("Problem computing column '{column}' for row '{key}'")
+                })?;
+                let value = serde_json::to_value(&value)?;
+                row.insert((*column).clone(), CtxDataType::from(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Join rows from other sources as described by `join`. `all_rows` holds the rows of every
+    /// source as read from file, with computed columns but without joins and filters.
+    pub fn apply_joins(
+        &self,
+        source_rows: &mut DataSourceRows,
+        all_rows: &HashMap<String, DataSourceRows>,
+    ) -> Result<()> {
+        for join in self.join.iter().flatten() {
+            let other_rows = all_rows
+                .get(&join.source)
+                .with_context(|| format!("unknown source '{}' in join", join.source))?;
+            let mut index: HashMap<String, Vec<&HashMap<String, CtxDataType>>> = HashMap::new();
+            for (key, row) in other_rows {
+                let value = match &join.to {
+                    Some(column) => row.get(column).map(display_value),
+                    None => Some(key.clone()),
+                };
+                if let Some(value) = value.filter(|value| !value.is_empty()) {
+                    index.entry(value).or_default().push(row);
+                }
+            }
+
+            let mut unmatched = HashSet::new();
+            for (key, row) in source_rows.iter_mut() {
+                let value = match &join.on {
+                    Some(column) => row.get(column).map(display_value).unwrap_or_default(),
+                    None => key.clone(),
+                };
+                let matches = index.get(&value).map(Vec::as_slice).unwrap_or_default();
+                if join.kind == JoinType::Many {
+                    let column = join.as_.clone().unwrap_or_else(|| join.source.clone());
+                    let list = matches.iter().map(|other| row_as_map(other)).collect();
+                    row.insert(column, CtxDataType::List(list));
+                    continue;
+                }
+                let other = match matches {
+                    [] => {
+                        if join.kind == JoinType::Inner {
+                            unmatched.insert(key.clone());
+                        } else if let Some(column) = &join.as_ {
+                            row.insert(column.clone(), CtxDataType::Empty);
+                        }
+                        continue;
+                    }
+                    [other] => other,
+                    _ => bail!(
+                        "row '{key}' matches {} rows in source '{}', use type 'many' to join them all",
+                        matches.len(),
+                        join.source
+                    ),
+                };
+                match &join.as_ {
+                    Some(column) => {
+                        row.insert(column.clone(), row_as_map(other));
+                    }
+                    None => {
+                        for (column, value) in *other {
+                            if column != META_COLUMN {
+                                row.entry(column.clone()).or_insert_with(|| value.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            source_rows.retain(|key, _| !unmatched.contains(key));
+        }
+        Ok(())
+    }
+}
+
+/// A source row as a map value with the columns sorted by name, without the `_meta` column
+fn row_as_map(row: &HashMap<String, CtxDataType>) -> CtxDataType {
+    let mut map: IndexMap<String, CtxDataType> = row
+        .iter()
+        .filter(|(column, _)| *column != META_COLUMN)
+        .map(|(column, value)| (column.clone(), value.clone()))
+        .collect();
+    map.sort_keys();
+    CtxDataType::Map(map)
+}
+
+impl Sheet {
+    /// All sheet names or patterns in the order they were given
+    pub fn to_vec(&self) -> Vec<&str> {
+        match self {
+            Sheet::Single(sheet) => vec![sheet.as_str()],
+            Sheet::Multiple(sheets) => sheets.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl From<&str> for Sheet {
+    fn from(s: &str) -> Sheet {
+        Sheet::Single(s.to_string())
+    }
+}
+
 impl From<&str> for Filename {
     fn from(s: &str) -> Filename {
         Filename::Single(s.to_string())
@@ -215,6 +692,9 @@ macro_rules! impl_row_filtering {
             fn get_exclude(&self) -> &Option<Vec<Include>> {
                 &self.exclude
             }
+            fn get_sort_by(&self) -> &Option<Vec<SortKey>> {
+                &self.sort_by
+            }
         }
     };
 }
@@ -225,19 +705,44 @@ impl_row_filtering!(Template);
 impl Config {
     #[allow(clippy::missing_errors_doc)]
     pub fn new(filename: &Path) -> Result<Self> {
-        let content = fs::read_to_string(filename)?;
-        let cfg: Self = serde_yaml::from_str(&content)?;
+        let composed = compose::compose(filename)?;
+        let mut cfg: Self = serde_yaml::from_value(serde_yaml::Value::Mapping(composed.mapping))?;
+        cfg.files = composed.files;
 
         if let Some(sources) = &cfg.sources {
             for source in sources {
                 validate_source(source)?;
             }
+            validate_joins(sources)?;
         }
 
         validate_encoding(&cfg.encoding)?;
+        validate_outputs(&cfg)?;
 
         Ok(cfg)
     }
+
+    /// All files to render: the top-level layout if not empty, followed by all outputs
+    pub fn targets(&self) -> Vec<Target<'_>> {
+        let mut targets = Vec::new();
+        if !self.layout.is_empty() {
+            targets.push(Target {
+                outputfile: self.outputfile.as_deref(),
+                encoding: &self.encoding,
+                globals: None,
+                layout: &self.layout,
+            });
+        }
+        for output in self.outputs.iter().flatten() {
+            targets.push(Target {
+                outputfile: output.outputfile.as_deref(),
+                encoding: output.encoding.as_deref().unwrap_or(&self.encoding),
+                globals: output.globals.as_ref(),
+                layout: &output.layout,
+            });
+        }
+        targets
+    }
 }
 
 pub fn include_exclude_set(
@@ -280,6 +785,107 @@ pub fn include_exclude_set(
     Ok(result)
 }
 
+/// Sort rows by the columns in `sort_by`, where later columns decide between rows that are
+/// equal in the earlier ones. Empty values come last, and rows that are equal keep their order.
+fn sort_rows(rows: &mut DataSourceRows, sort_by: &[SortKey]) {
+    rows.sort_by(|_, a, _, b| {
+        sort_by
+            .iter()
+            .map(|key| {
+                let (column, order, compare) = key.spec();
+                let a = a.get(column).unwrap_or(&CtxDataType::Empty);
+                let b = b.get(column).unwrap_or(&CtxDataType::Empty);
+                // Values that can not be compared come last regardless of the order
+                let ordering = match compare {
+                    SortCompare::Natural => {
+                        let (a, b) = (display_value(a), display_value(b));
+                        if a.is_empty() || b.is_empty() {
+                            return a.is_empty().cmp(&b.is_empty());
+                        }
+                        natural_cmp(&a, &b)
+                    }
+                    SortCompare::Numeric => match (as_number(a), as_number(b)) {
+                        (Some(a), Some(b)) => a.total_cmp(&b),
+                        (a, b) => return a.is_none().cmp(&b.is_none()),
+                    },
+                };
+                match order {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn as_number(value: &CtxDataType) -> Option<f64> {
+    match value {
+        CtxDataType::Int(i) => Some(*i as f64),
+        CtxDataType::Float(f) => Some(*f),
+        CtxDataType::String(s) => s.trim().replace(',', ".").parse().ok(),
+        _ => None,
+    }
+}
+
+/// Compare strings so that runs of digits are compared by their numeric value
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    fn chunks(s: &str) -> Vec<(bool, &str)> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for (i, c) in s.char_indices().skip(1) {
+            let prev = s[..i].chars().next_back().unwrap_or(c);
+            if prev.is_ascii_digit() != c.is_ascii_digit() {
+                chunks.push(&s[start..i]);
+                start = i;
+            }
+        }
+        if start < s.len() {
+            chunks.push(&s[start..]);
+        }
+        chunks
+            .into_iter()
+            .map(|chunk| (chunk.starts_with(|c: char| c.is_ascii_digit()), chunk))
+            .collect()
+    }
+    for (x, y) in chunks(a).into_iter().zip(chunks(b)) {
+        let ordering = match (x, y) {
+            ((true, x), (true, y)) => {
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()))
+            }
+            ((_, x), (_, y)) => x.cmp(y),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    chunks(a).len().cmp(&chunks(b).len())
+}
+
+fn validate_joins(sources: &[Source]) -> Result<()> {
+    for source in sources {
+        for join in source.join.iter().flatten() {
+            if join.source == source.id {
+                bail!("source '{}' can not join with itself", source.id);
+            }
+            if !sources.iter().any(|other| other.id == join.source) {
+                bail!(
+                    "unknown source '{}' in join for source '{}'",
+                    join.source,
+                    source.id
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 fn validate_encoding(encoding: &str) -> Result<()> {
     if encoding_rs::Encoding::for_label(encoding.as_bytes()).is_none() {
         bail!("invalid encoding '{}'", encoding);
@@ -287,41 +893,141 @@ fn validate_encoding(encoding: &str) -> Result<()> {
     Ok(())
 }
 
-fn validate_source(source: &Source) -> Result<()> {
-    match &source.filename {
-        Filename::Single(filename) => {
-            let extension = Path::new(filename).extension();
-            match extension {
-                Some(ext) if ext == "xlsx" => {
-                    if source.sheet.is_none() {
-                        bail!("missing field 'sheet' for .xlsx source '{}'", source.id);
-                    }
-                    if source.delimiter.is_some() {
-                        bail!("field 'delimiter' invalid for .xlsx source '{}'", source.id);
-                    }
-                }
-                Some(ext) if ext == "csv" => {
-                    if source.sheet.is_some() {
-                        bail!("field 'sheet' invalid for .csv source '{}'", source.id);
-                    }
-                }
-                _ => {
-                    bail!("invalid file extension for source '{}'", source.id);
-                }
-            }
-        }
-        Filename::Multiple(filenames) => {
-            if filenames.iter().any(|filename| {
-                Path::new(filename).extension().and_then(|s| s.to_str()) != Some("csv")
-            }) {
+fn validate_outputs(cfg: &Config) -> Result<()> {
+    if cfg.layout.is_empty() && cfg.outputs.is_none() {
+        bail!("missing field 'layout' or 'outputs'");
+    }
+    if cfg.outputfile.is_some() && cfg.layout.is_empty() {
+        bail!("field 'outputfile' requires a top-level 'layout'");
+    }
+    let mut outputfiles = HashSet::new();
+    for target in cfg.targets() {
+        validate_encoding(target.encoding)?;
+        for template in target.layout {
+            if template.group_by.is_some() && template.source.is_none() {
                 bail!(
-                    "All files in multi-file source '{}' must be .csv",
-                    source.id
+                    "field 'group_by' requires 'source' in template '{}'",
+                    template.name
                 );
             }
-            if source.sheet.is_some() {
-                bail!("field 'sheet' invalid for .csv sources in '{}'", source.id);
+        }
+        if let Some(outputfile) = target.outputfile
+            && !outputfiles.insert(outputfile)
+        {
+            bail!("outputfile '{}' is specified more than once", outputfile);
+        }
+    }
+    Ok(())
+}
+
+fn validate_source(source: &Source) -> Result<()> {
+    let filenames = source.filename.to_vec();
+    let mut extensions = Vec::new();
+    for filename in &filenames {
+        match Path::new(filename).extension().and_then(|ext| ext.to_str()) {
+            Some(ext @ ("csv" | "json" | "yaml" | "yml" | "db" | "sqlite")) => {
+                extensions.push(ext);
             }
+            Some(ext) if SPREADSHEET_EXTENSIONS.contains(&ext) => extensions.push(ext),
+            _ => bail!("invalid file extension for source '{}'", source.id),
+        }
+    }
+    let has = |exts: &[&str]| extensions.iter().any(|ext| exts.contains(ext));
+    // Used in messages about fields that are invalid for all files in the source
+    let ext = extensions.first().copied().unwrap_or_default();
+
+    if let Some(spreadsheet) = extensions
+        .iter()
+        .find(|ext| SPREADSHEET_EXTENSIONS.contains(ext))
+    {
+        // A named range identifies the sheet by itself
+        if source.sheet.is_none() && source.range.is_none() {
+            bail!(
+                "missing field 'sheet' for .{spreadsheet} source '{}'",
+                source.id
+            );
+        }
+    } else if source.sheet.is_some() {
+        bail!("field 'sheet' invalid for .{ext} source '{}'", source.id);
+    }
+    if source.on_cell_error.is_some() && !has(&SPREADSHEET_EXTENSIONS) {
+        bail!(
+            "field 'on_cell_error' invalid for .{ext} source '{}'",
+            source.id
+        );
+    }
+    if source.delimiter.is_some() && !has(&["csv"]) {
+        bail!(
+            "field 'delimiter' invalid for .{ext} source '{}'",
+            source.id
+        );
+    }
+    if source.key.as_ref().is_some_and(Vec::is_empty) {
+        bail!("field 'key' must not be empty in source '{}'", source.id);
+    }
+    if source.key_separator.is_some() && source.key.as_ref().is_none_or(|key| key.len() < 2) {
+        bail!(
+            "field 'key_separator' requires a composite 'key' in source '{}'",
+            source.id
+        );
+    }
+    for (field, value) in [
+        ("header_row", source.header_row),
+        ("data_start_row", source.data_start_row),
+    ] {
+        if value.is_some() && !has(&SPREADSHEET_EXTENSIONS) && !has(&["csv"]) {
+            bail!("field '{field}' invalid for .{ext} source '{}'", source.id);
+        }
+        if value == Some(0) {
+            bail!(
+                "field '{field}' must be 1 or more in source '{}'",
+                source.id
+            );
+        }
+    }
+    if let (Some(header_row), Some(data_start_row)) = (source.header_row, source.data_start_row)
+        && data_start_row <= header_row
+    {
+        bail!(
+            "field 'data_start_row' must be after 'header_row' in source '{}'",
+            source.id
+        );
+    }
+    if source.range.is_some() && !has(&SPREADSHEET_EXTENSIONS) {
+        bail!("field 'range' invalid for .{ext} source '{}'", source.id);
+    }
+    if has(&["db", "sqlite"]) {
+        if source.query.is_none() {
+            bail!("missing field 'query' for .{ext} source '{}'", source.id);
+        }
+    } else if source.query.is_some() {
+        bail!(
+            "field 'query' is only valid for .db and .sqlite source '{}'",
+            source.id
+        );
+    }
+    for (name, column) in source.columns.iter().flatten() {
+        if column.format.is_some() && column.kind != Some(ColumnType::DateTime) {
+            bail!(
+                "field 'format' requires type 'datetime' for column '{name}' in source '{}'",
+                source.id
+            );
+        }
+        if let Some(pattern) = &column.pattern
+            && let Err(e) = regex::Regex::new(pattern)
+        {
+            bail!(
+                "invalid 'pattern' for column '{name}' in source '{}': {e}",
+                source.id
+            );
+        }
+        if let (Some(default), Some(kind)) = (&column.default, column.kind)
+            && coerce(&default.clone().into(), kind, column.format.as_deref()).is_none()
+        {
+            bail!(
+                "'default' for column '{name}' in source '{}' is not a valid {kind}",
+                source.id
+            );
         }
     }
     Ok(())
@@ -330,7 +1036,6 @@ fn validate_source(source: &Source) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use indexmap::IndexMap;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -489,6 +1194,108 @@ layout:
         }
     }
 
+    #[test]
+    fn config_outputs_become_targets() {
+        let content = r#"
+outputfile: main.cnfg
+templatepath: templates
+encoding: utf-8
+layout:
+  - name: main.tmpl
+outputs:
+  - outputfile: display.cnfg
+    encoding: Windows-1252
+    globals:
+      display: true
+    layout:
+      - name: display.tmpl
+  - outputfile: testrig.cnfg
+    layout:
+      - name: main.tmpl
+"#;
+        let temp_file = create_temp_yaml(content);
+        let config = Config::new(temp_file.path()).unwrap();
+        let targets = config.targets();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[0].outputfile, Some("main.cnfg"));
+        assert!(targets[0].globals.is_none());
+        assert_eq!(targets[1].encoding, "Windows-1252");
+        assert_eq!(
+            targets[1].globals.unwrap()["display"],
+            serde_json::Value::Bool(true)
+        );
+        assert_eq!(targets[2].encoding, "utf-8");
+        assert_eq!(targets[2].layout[0].name, "main.tmpl");
+    }
+
+    #[test]
+    fn config_outputs_without_top_level_layout() {
+        let content = r#"
+templatepath: templates
+outputs:
+  - outputfile: display.cnfg
+    layout:
+      - name: display.tmpl
+"#;
+        let temp_file = create_temp_yaml(content);
+        let config = Config::new(temp_file.path()).unwrap();
+        assert_eq!(config.targets().len(), 1);
+    }
+
+    #[test]
+    fn config_fail_on_missing_layout_and_outputs() {
+        let temp_file = create_temp_yaml("templatepath: templates");
+        let config = Config::new(temp_file.path());
+        assert!(config.is_err());
+        assert!(
+            config
+                .unwrap_err()
+                .to_string()
+                .contains("missing field 'layout' or 'outputs'")
+        );
+    }
+
+    #[test]
+    fn config_fail_on_duplicate_outputfile() {
+        let content = r#"
+outputfile: main.cnfg
+templatepath: templates
+layout:
+  - name: main.tmpl
+outputs:
+  - outputfile: main.cnfg
+    layout:
+      - name: display.tmpl
+"#;
+        let temp_file = create_temp_yaml(content);
+        let config = Config::new(temp_file.path());
+        assert!(config.is_err());
+        assert!(
+            config
+                .unwrap_err()
+                .to_string()
+                .contains("outputfile 'main.cnfg' is specified more than once")
+        );
+    }
+
+    #[test]
+    fn config_fail_on_group_by_without_source() {
+        let content = r#"
+templatepath: templates
+layout:
+  - name: manifold.tmpl
+    group_by: manifold
+"#;
+        let temp_file = create_temp_yaml(content);
+        let config = Config::new(temp_file.path());
+        assert!(
+            config
+                .unwrap_err()
+                .to_string()
+                .contains("field 'group_by' requires 'source' in template 'manifold.tmpl'")
+        );
+    }
+
     #[test]
     fn fail_validate_encoding_unknown() {
         let result = validate_encoding("unknown");
@@ -516,7 +1323,7 @@ layout:
         let source = Source {
             filename: "data.xlsx".into(),
             id: "id".to_string(),
-            sheet: Some("sheet".to_string()),
+            sheet: Some("sheet".into()),
             ..Default::default()
         };
         assert!(validate_source(&source).is_ok())
@@ -544,7 +1351,7 @@ layout:
         let source = Source {
             filename: "data.xlsx".into(),
             id: "id".to_string(),
-            sheet: Some("sheet".to_string()),
+            sheet: Some("sheet".into()),
             delimiter: Some(':'),
             ..Default::default()
         };
@@ -569,12 +1376,124 @@ layout:
         assert!(validate_source(&source).is_ok())
     }
 
+    #[test]
+    fn validate_source_good_json_and_yaml() {
+        for filename in ["data.json", "data.yaml", "data.yml"] {
+            let source = Source {
+                filename: filename.into(),
+                id: "id".to_string(),
+                key: Some(vec!["tag".to_string()]),
+                ..Default::default()
+            };
+            assert!(validate_source(&source).is_ok())
+        }
+    }
+
+    #[test]
+    fn validate_source_sqlite_requires_query() {
+        let mut source = Source {
+            filename: "tags.db".into(),
+            id: "id".to_string(),
+            ..Default::default()
+        };
+        let result = validate_source(&source);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("missing field 'query'")
+        );
+        source.query = Some("SELECT * FROM tags".to_string());
+        assert!(validate_source(&source).is_ok());
+        source.filename = "tags.csv".into();
+        let result = validate_source(&source);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("field 'query' is only valid")
+        );
+    }
+
+    #[test]
+    fn validate_source_good_table_layout() {
+        let source = Source {
+            filename: "data.xlsx".into(),
+            id: "id".to_string(),
+            key: Some(vec!["Area".to_string(), "Tag".to_string()]),
+            key_separator: Some("/".to_string()),
+            header_row: Some(3),
+            data_start_row: Some(5),
+            range: Some("TagTable".to_string()),
+            ..Default::default()
+        };
+        assert!(validate_source(&source).is_ok())
+    }
+
+    #[test]
+    fn fail_validate_source_data_start_row_before_header_row() {
+        let source = Source {
+            filename: "data.csv".into(),
+            id: "id".to_string(),
+            header_row: Some(3),
+            data_start_row: Some(3),
+            ..Default::default()
+        };
+        let result = validate_source(&source);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("'data_start_row' must be after 'header_row'")
+        )
+    }
+
+    #[test]
+    fn fail_validate_source_invalid_columns() {
+        for (columns, expected) in [
+            (
+                "tag: { pattern: '[A-Z' }",
+                "invalid 'pattern' for column 'tag'",
+            ),
+            (
+                "size: { type: int, default: large }",
+                "'default' for column 'size' in source 'id' is not a valid int",
+            ),
+        ] {
+            let source = Source {
+                filename: "data.csv".into(),
+                id: "id".to_string(),
+                columns: Some(serde_yaml::from_str(columns).unwrap()),
+                ..Default::default()
+            };
+            let result = validate_source(&source);
+            assert!(result.unwrap_err().to_string().contains(expected));
+        }
+    }
+
+    #[test]
+    fn fail_validate_source_json_with_header_row() {
+        let source = Source {
+            filename: "data.json".into(),
+            id: "id".to_string(),
+            header_row: Some(2),
+            ..Default::default()
+        };
+        let result = validate_source(&source);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("field 'header_row' invalid for .json source")
+        )
+    }
+
     #[test]
     fn fail_validate_source_csv_with_sheet() {
         let source = Source {
             filename: "data.csv".into(),
             id: "id".to_string(),
-            sheet: Some("sheet".to_string()),
+            sheet: Some("sheet".into()),
             ..Default::default()
         };
         let result = validate_source(&source);
@@ -599,7 +1518,19 @@ layout:
     }
 
     #[test]
-    fn fail_validate_multisource_with_xlsx() {
+    fn validate_multisource_good_csv_and_xlsx() {
+        let source = Source {
+            filename: vec!["data1.csv", "data2.xlsx"].into(),
+            id: "id".to_string(),
+            delimiter: Some(':'),
+            sheet: Some("Wells_*".into()),
+            ..Default::default()
+        };
+        assert!(validate_source(&source).is_ok())
+    }
+
+    #[test]
+    fn fail_validate_multisource_with_xlsx_no_sheet() {
         let source = Source {
             filename: vec!["data1.csv", "data2.xlsx"].into(),
             id: "id".to_string(),
@@ -612,16 +1543,41 @@ layout:
             result
                 .unwrap_err()
                 .to_string()
-                .contains("in multi-file source 'id' must be .csv"),
+                .contains("missing field 'sheet' for .xlsx source 'id'"),
         )
     }
 
+    #[test]
+    fn validate_source_legacy_spreadsheets() {
+        for filename in ["data.xlsm", "data.xls", "data.ods"] {
+            let source = Source {
+                filename: filename.into(),
+                id: "id".to_string(),
+                on_cell_error: Some(OnCellError::Empty),
+                ..Default::default()
+            };
+            let result = validate_source(&source);
+            let ext = filename.trim_start_matches("data.");
+            assert!(
+                result
+                    .unwrap_err()
+                    .to_string()
+                    .contains(&format!("missing field 'sheet' for .{ext} source 'id'")),
+            );
+            let source = Source {
+                sheet: Some("Wells_*".into()),
+                ..source
+            };
+            assert!(validate_source(&source).is_ok());
+        }
+    }
+
     #[test]
     fn fail_validate_multisource_with_sheet() {
         let source = Source {
             filename: vec!["data1.csv", "data2.csv"].into(),
             id: "id".to_string(),
-            sheet: Some("sheet".to_string()),
+            sheet: Some("sheet".into()),
             ..Default::default()
         };
         let result = validate_source(&source);
@@ -706,4 +1662,262 @@ layout:
         .unwrap();
         assert!(res == HashSet::from(["one".to_string(), "two".to_string(), "three".to_string()]));
     }
+
+    fn computed_source(computed: &str) -> Source {
+        Source {
+            filename: "data.csv".into(),
+            id: "id".to_string(),
+            computed: Some(serde_yaml::from_str(computed).unwrap()),
+            include: Some(vec![Include::Conditional(IncludeConditional {
+                condition: "high_limit > 100".to_string(),
+                items: None,
+                continue_: None,
+            })]),
+            ..Default::default()
+        }
+    }
+
+    fn rows_with_tags() -> DataSourceRows {
+        let mut rows = DataSourceRows::new();
+        for (tag, high) in [("FI-101", 50), ("FI-102", 80)] {
+            let row = HashMap::from([
+                ("tag".to_string(), CtxDataType::String(tag.to_string())),
+                ("high".to_string(), CtxDataType::Int(high)),
+            ]);
+            rows.insert(tag.to_string(), row);
+        }
+        rows
+    }
+
+    #[test]
+    fn apply_computed_adds_columns_before_filters() {
+        let source = computed_source(
+            r#"
+pv_tag: tag ~ "_PV"
+high_limit: high * 1.5
+label: pv_tag | lower
+"#,
+        );
+        let env = Environment::new();
+        let mut rows = rows_with_tags();
+        source.apply_computed(&mut rows, &env).unwrap();
+
+        let row = &rows["FI-101"];
+        assert_eq!(row["pv_tag"], CtxDataType::String("FI-101_PV".to_string()));
+        assert_eq!(row["high_limit"], CtxDataType::Float(75.0));
+        assert_eq!(row["label"], CtxDataType::String("fi-101_pv".to_string()));
+
+        let filtered = source.apply_filters(&rows, &env).unwrap();
+        assert_eq!(filtered.keys().collect::<Vec<_>>(), ["FI-102"]);
+    }
+
+    #[test]
+    fn fail_apply_computed_invalid_expression() {
+        let source = computed_source("pv_tag: tag ~");
+        let result = source.apply_computed(&mut rows_with_tags(), &Environment::new());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid expression for computed column 'pv_tag'")
+        );
+    }
+
+    fn rows(items: &[(&str, &[(&str, &str)])]) -> DataSourceRows {
+        items
+            .iter()
+            .map(|(key, columns)| {
+                let row = columns
+                    .iter()
+                    .map(|(column, value)| {
+                        (column.to_string(), CtxDataType::String(value.to_string()))
+                    })
+                    .collect();
+                (key.to_string(), row)
+            })
+            .collect()
+    }
+
+    fn join_sources() -> HashMap<String, DataSourceRows> {
+        HashMap::from([
+            (
+                "wells".to_string(),
+                rows(&[
+                    ("W1", &[("well", "W1"), ("area", "north")]),
+                    ("W2", &[("well", "W2"), ("area", "south")]),
+                    ("W3", &[("well", "W3"), ("area", "north")]),
+                ]),
+            ),
+            (
+                "coords".to_string(),
+                rows(&[
+                    ("W1", &[("well", "W1"), ("x", "10")]),
+                    (
+                        "W2",
+                        &[("well", "W2"), ("x", "20"), (META_COLUMN, "coords.csv")],
+                    ),
+                ]),
+            ),
+            (
+                "tags".to_string(),
+                rows(&[
+                    ("FI1", &[("tag", "FI1"), ("well", "W1")]),
+                    ("PI1", &[("tag", "PI1"), ("well", "W1")]),
+                    ("FI2", &[("tag", "FI2"), ("well", "W2")]),
+                ]),
+            ),
+        ])
+    }
+
+    fn joined(join: Join) -> Result<DataSourceRows> {
+        let all_rows = join_sources();
+        let source = Source {
+            id: "wells".to_string(),
+            join: Some(vec![join]),
+            ..Default::default()
+        };
+        let mut wells = all_rows["wells"].clone();
+        source.apply_joins(&mut wells, &all_rows)?;
+        Ok(wells)
+    }
+
+    #[test]
+    fn apply_joins_left_and_inner() {
+        let left = joined(Join {
+            source: "coords".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(left.len(), 3);
+        assert_eq!(left["W1"]["x"], CtxDataType::String("10".to_string()));
+        assert!(!left["W3"].contains_key("x"));
+        assert!(!left["W2"].contains_key(META_COLUMN));
+
+        let inner = joined(Join {
+            source: "coords".to_string(),
+            on: Some("well".to_string()),
+            to: Some("well".to_string()),
+            kind: JoinType::Inner,
+            as_: Some("coord".to_string()),
+        })
+        .unwrap();
+        assert_eq!(inner.keys().collect::<Vec<_>>(), ["W1", "W2"]);
+        let CtxDataType::Map(coord) = &inner["W2"]["coord"] else {
+            panic!("expected a map");
+        };
+        assert_eq!(coord["x"], CtxDataType::String("20".to_string()));
+        assert!(!coord.contains_key(META_COLUMN));
+    }
+
+    #[test]
+    fn apply_joins_many() {
+        let many = joined(Join {
+            source: "tags".to_string(),
+            to: Some("well".to_string()),
+            kind: JoinType::Many,
+            ..Default::default()
+        })
+        .unwrap();
+        let tag_count = |key: &str| match &many[key]["tags"] {
+            CtxDataType::List(tags) => tags.len(),
+            _ => panic!("expected a list"),
+        };
+        assert_eq!(
+            [tag_count("W1"), tag_count("W2"), tag_count("W3")],
+            [2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn fail_apply_joins_multiple_matches() {
+        let result = joined(Join {
+            source: "tags".to_string(),
+            to: Some("well".to_string()),
+            ..Default::default()
+        });
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("row 'W1' matches 2 rows in source 'tags'")
+        );
+    }
+
+    #[test]
+    fn fail_validate_joins_unknown_source() {
+        let sources = [Source {
+            id: "wells".to_string(),
+            join: Some(vec![Join {
+                source: "coords".to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }];
+        let result = validate_joins(&sources);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("unknown source 'coords' in join for source 'wells'")
+        );
+    }
+
+    #[test]
+    fn natural_cmp_compares_digits_as_numbers() {
+        use std::cmp::Ordering;
+        assert_eq!(natural_cmp("FI-2", "FI-10"), Ordering::Less);
+        assert_eq!(natural_cmp("FI-10", "FI-10A"), Ordering::Less);
+        assert_eq!(natural_cmp("PI-1", "FI-2"), Ordering::Greater);
+        assert_eq!(natural_cmp("D02", "D2"), Ordering::Greater);
+        assert_eq!(natural_cmp("W7", "W7"), Ordering::Equal);
+    }
+
+    #[test]
+    fn apply_filters_sorts_after_filtering() {
+        let mut source_rows = DataSourceRows::new();
+        for (tag, area, priority) in [
+            ("FI-10", "north", CtxDataType::Int(2)),
+            ("FI-2", "south", CtxDataType::String("10".to_string())),
+            ("FI-3", "north", CtxDataType::Float(2.0)),
+            ("PI-1", "north", CtxDataType::Empty),
+            ("TI-1", "north", CtxDataType::Int(9)),
+        ] {
+            let row = HashMap::from([
+                ("tag".to_string(), CtxDataType::String(tag.to_string())),
+                ("area".to_string(), CtxDataType::String(area.to_string())),
+                ("priority".to_string(), priority),
+            ]);
+            source_rows.insert(tag.to_string(), row);
+        }
+        let template: Template = serde_yaml::from_str(
+            r#"
+name: tags.tmpl
+exclude: [TI-1]
+sort_by:
+  - area
+  - column: priority
+    order: desc
+    compare: numeric
+  - tag
+"#,
+        )
+        .unwrap();
+        let rows = template
+            .apply_filters(&source_rows, &Environment::new())
+            .unwrap();
+        assert_eq!(
+            rows.keys().collect::<Vec<_>>(),
+            ["FI-3", "FI-10", "PI-1", "FI-2"]
+        );
+
+        let template: Template =
+            serde_yaml::from_str("name: tags.tmpl\nsort_by: [area, priorty]").unwrap();
+        assert_eq!(
+            template
+                .apply_filters(&source_rows, &Environment::new())
+                .unwrap_err()
+                .to_string(),
+            "unknown column 'priorty' in sort_by"
+        );
+    }
 }
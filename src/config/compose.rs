@@ -0,0 +1,388 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::ListMerge;
+use super::MergeRules;
+
+/// A config file with all `extends` and `include` files merged into it
+pub(super) struct Composed {
+    pub mapping: Mapping,
+    /// The config file itself followed by every file it extends or includes
+    pub files: Vec<PathBuf>,
+}
+
+/// Read a config file and merge it with the files it extends and includes. Relative paths in
+/// every file are rewritten so that they are relative to the directory of `filename`.
+pub(super) fn compose(filename: &Path) -> Result<Composed> {
+    let root = filename.parent().unwrap_or(Path::new(""));
+    let mut composed = Composed {
+        mapping: Mapping::new(),
+        files: Vec::new(),
+    };
+    let (mapping, _merge) = load(filename, root, &mut Vec::new(), &mut composed.files)?;
+    composed.mapping = mapping;
+    Ok(composed)
+}
+
+fn load(
+    filename: &Path,
+    root: &Path,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<(Mapping, MergeRules)> {
+    let canonical = fs::canonicalize(filename)
+        .map_err(|e| anyhow!("Unable to read '{}': {e}", filename.display()))?;
+    if stack.contains(&canonical) {
+        bail!("circular extends or include of '{}'", filename.display());
+    }
+    stack.push(canonical);
+    files.push(filename.to_path_buf());
+
+    let content = fs::read_to_string(filename)?;
+    let value: Value = serde_yaml::from_str(&content)
+        .with_context(|| format!("Problem parsing '{}'", filename.display()))?;
+    let mut mapping = match value {
+        Value::Mapping(mapping) => mapping,
+        Value::Null => Mapping::new(),
+        _ => bail!("'{}' does not contain a yaml mapping", filename.display()),
+    };
+
+    let dir = filename.parent().unwrap_or(Path::new(""));
+    rewrite_paths(&mut mapping, dir, root);
+
+    let extends = take_as::<String>(&mut mapping, "extends", filename)?;
+    let include = take_as::<Vec<String>>(&mut mapping, "include", filename)?;
+    let merge = take_as::<MergeRules>(&mut mapping, "merge", filename)?.unwrap_or_default();
+
+    let mut result = Mapping::new();
+    if let Some(extends) = extends {
+        let (base, _merge) = load(&dir.join(extends), root, stack, files)?;
+        result = base;
+    }
+    for fragment in include.unwrap_or_default() {
+        let (fragment, fragment_merge) = load(&dir.join(fragment), root, stack, files)?;
+        merge_into(&mut result, fragment, &fragment_merge)?;
+    }
+    merge_into(&mut result, mapping, &merge)
+        .with_context(|| format!("Problem merging '{}'", filename.display()))?;
+
+    stack.pop();
+    Ok((result, merge))
+}
+
+fn take_as<T: serde::de::DeserializeOwned>(
+    mapping: &mut Mapping,
+    key: &str,
+    filename: &Path,
+) -> Result<Option<T>> {
+    mapping
+        .remove(key)
+        .map(|value| {
+            serde_yaml::from_value(value)
+                .with_context(|| format!("Invalid '{key}' in '{}'", filename.display()))
+        })
+        .transpose()
+}
+
+/// Merge `other` into `base`. Scalars and maps in `other` replace those in `base`, while the
+/// top-level lists that have a field in `MergeRules` are combined according to `rules`.
+fn merge_into(base: &mut Mapping, other: Mapping, rules: &MergeRules) -> Result<()> {
+    for (key, value) in other {
+        let list = key
+            .as_str()
+            .and_then(|list_key| Some((list_key, rules.get(list_key)?)));
+        match (list, base.get_mut(&key), value) {
+            (Some((list_key, rule)), Some(Value::Sequence(base_items)), Value::Sequence(items)) => {
+                merge_list(list_key, base_items, items, rule)?;
+            }
+            (_, _, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn merge_list(
+    list_key: &str,
+    base_items: &mut Vec<Value>,
+    items: Vec<Value>,
+    rule: &ListMerge,
+) -> Result<()> {
+    let position = |name: &str| {
+        base_items
+            .iter()
+            .position(|item| item_name(list_key, item) == Some(name))
+            .with_context(|| format!("'{name}' not found in inherited '{list_key}'"))
+    };
+    match rule {
+        ListMerge::Replace => *base_items = items,
+        ListMerge::Append => {
+            for item in items {
+                // Sources and counters are identified by id and name, so a new definition
+                // replaces the inherited one
+                let existing = match list_key {
+                    "sources" | "counters" => item_name(list_key, &item).and_then(|name| {
+                        base_items
+                            .iter()
+                            .position(|base_item| item_name(list_key, base_item) == Some(name))
+                    }),
+                    _ => None,
+                };
+                match existing {
+                    Some(index) => base_items[index] = item,
+                    None => base_items.push(item),
+                }
+            }
+        }
+        ListMerge::Before(name) => {
+            let index = position(name)?;
+            base_items.splice(index..index, items);
+        }
+        ListMerge::After(name) => {
+            let index = position(name)? + 1;
+            base_items.splice(index..index, items);
+        }
+    }
+    Ok(())
+}
+
+/// The field that identifies an item in one of the lists in `MergeRules`
+fn item_name<'a>(list_key: &str, item: &'a Value) -> Option<&'a str> {
+    let field = match list_key {
+        "sources" => "id",
+        "drawio" => "input",
+        "outputs" => "outputfile",
+        _ => "name",
+    };
+    item.get(field).and_then(Value::as_str)
+}
+
+/// Rewrite the relative paths in a config file located in `dir` so they become relative to
+/// `root`, the directory of the config file given on the command line.
+fn rewrite_paths(mapping: &mut Mapping, dir: &Path, root: &Path) {
+    let prefix = match dir.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => dir.to_path_buf(),
+    };
+    if prefix.as_os_str().is_empty() {
+        return;
+    }
+    let rewrite = |value: &mut Value| {
+        if let Value::String(path) = value
+            && Path::new(path).is_relative()
+        {
+            *path = prefix.join(&*path).to_string_lossy().to_string();
+        }
+    };
+
+    for key in ["outputfile", "templatepath", "macros"] {
+        match mapping.get_mut(key) {
+            Some(Value::Sequence(paths)) => paths.iter_mut().for_each(rewrite),
+            Some(value) => rewrite(value),
+            None => (),
+        }
+    }
+    let fields = [
+        ("sources", "filename"),
+        ("drawio", "input"),
+        ("drawio", "pngoutput"),
+        ("drawio", "csvoutput"),
+        ("outputs", "outputfile"),
+    ];
+    for (list_key, field) in fields {
+        let Some(Value::Sequence(items)) = mapping.get_mut(list_key) else {
+            continue;
+        };
+        for item in items {
+            match item.get_mut(field) {
+                Some(Value::Sequence(paths)) => paths.iter_mut().for_each(rewrite),
+                Some(value) => rewrite(value),
+                None => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_files(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn names(mapping: &Mapping, list_key: &str) -> Vec<String> {
+        mapping[list_key]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|item| item_name(list_key, item).unwrap().to_string())
+            .collect()
+    }
+
+    const BASE: &str = r#"
+outputfile: base.cnfg
+templatepath: templates
+macros:
+  - macros/mvr.jinja
+sources:
+  - filename: main.xlsx
+    id: main
+  - filename: extra.csv
+    id: extra
+layout:
+  - name: header.tmpl
+  - name: main.tmpl
+  - name: footer.tmpl
+"#;
+
+    #[test]
+    fn compose_extends_appends_and_overrides() {
+        let dir = write_files(&[
+            ("common/base.yaml", BASE),
+            (
+                "asset/asset.yaml",
+                r#"
+extends: ../common/base.yaml
+outputfile: asset.cnfg
+sources:
+  - filename: asset.xlsx
+    id: main
+layout:
+  - name: asset.tmpl
+"#,
+            ),
+        ]);
+        let composed = compose(&dir.path().join("asset/asset.yaml")).unwrap();
+        let mapping = &composed.mapping;
+        assert_eq!(mapping["outputfile"].as_str(), Some("asset.cnfg"));
+        assert_eq!(
+            mapping["templatepath"].as_str(),
+            Some("../common/templates")
+        );
+        assert_eq!(
+            mapping["macros"][0].as_str(),
+            Some("../common/macros/mvr.jinja")
+        );
+        assert_eq!(names(mapping, "sources"), ["main", "extra"]);
+        assert_eq!(
+            mapping["sources"][0]["filename"].as_str(),
+            Some("asset.xlsx")
+        );
+        assert_eq!(
+            mapping["sources"][1]["filename"].as_str(),
+            Some("../common/extra.csv")
+        );
+        assert_eq!(
+            names(mapping, "layout"),
+            ["header.tmpl", "main.tmpl", "footer.tmpl", "asset.tmpl"]
+        );
+        assert_eq!(composed.files.len(), 2);
+    }
+
+    #[test]
+    fn compose_merge_rules() {
+        let dir = write_files(&[
+            ("base.yaml", BASE),
+            (
+                "fragments/display.yaml",
+                r#"
+merge:
+  layout:
+    after: header.tmpl
+layout:
+  - name: display.tmpl
+"#,
+            ),
+            (
+                "config.yaml",
+                r#"
+extends: base.yaml
+include:
+  - fragments/display.yaml
+merge:
+  sources: replace
+  layout:
+    before: footer.tmpl
+sources:
+  - filename: other.csv
+    id: other
+layout:
+  - name: extra.tmpl
+"#,
+            ),
+        ]);
+        let composed = compose(&dir.path().join("config.yaml")).unwrap();
+        assert_eq!(names(&composed.mapping, "sources"), ["other"]);
+        assert_eq!(
+            names(&composed.mapping, "layout"),
+            [
+                "header.tmpl",
+                "display.tmpl",
+                "main.tmpl",
+                "extra.tmpl",
+                "footer.tmpl"
+            ]
+        );
+        assert_eq!(composed.files.len(), 3);
+    }
+
+    #[test]
+    fn compose_fails_on_unknown_template_in_merge() {
+        let dir = write_files(&[
+            ("base.yaml", BASE),
+            (
+                "config.yaml",
+                "extends: base.yaml\nmerge:\n  layout:\n    after: missing.tmpl\nlayout:\n  - name: a.tmpl\n",
+            ),
+        ]);
+        let result = compose(&dir.path().join("config.yaml"));
+        assert!(
+            format!("{:#}", result.err().unwrap())
+                .contains("'missing.tmpl' not found in inherited 'layout'")
+        );
+    }
+
+    #[test]
+    fn compose_fails_on_unknown_list_in_merge() {
+        let dir = write_files(&[
+            ("base.yaml", BASE),
+            (
+                "config.yaml",
+                "extends: base.yaml\nmerge:\n  layuot: replace\nlayout:\n  - name: a.tmpl\n",
+            ),
+        ]);
+        let message = format!(
+            "{:#}",
+            compose(&dir.path().join("config.yaml")).err().unwrap()
+        );
+        assert!(message.contains("Invalid 'merge'"), "{message}");
+        assert!(message.contains("unknown field `layuot`"), "{message}");
+    }
+
+    #[test]
+    fn compose_fails_on_circular_extends() {
+        let dir = write_files(&[
+            ("a.yaml", "extends: b.yaml\n"),
+            ("b.yaml", "extends: a.yaml\n"),
+        ]);
+        let result = compose(&dir.path().join("a.yaml"));
+        assert!(
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("circular extends or include")
+        );
+    }
+}
@@ -1,18 +1,27 @@
-use anyhow::{Context, Result, bail};
-use calamine::{CellErrorType, Data, DataType, Reader, Xlsx, open_workbook};
+use anyhow::{Context, Result, anyhow, bail};
+use calamine::{CellErrorType, Data, Reader, Sheets, open_workbook_auto};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
 use csv::{self, Trim};
 use indexmap::IndexMap;
-use serde::Serialize;
-use std::collections::HashMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::sync::LazyLock;
+
+use crate::config::{Column, ColumnType, Combine, OnCellError};
 use std::path::{Path, PathBuf};
 
 pub type DataSourceRows = IndexMap<String, HashMap<String, CtxDataType>>;
 
+/// Reserved column with the source id, file, sheet, row number and key of each row
+pub const META_COLUMN: &str = "_meta";
+
 pub trait DataSourceReader {
     fn read(&self) -> Result<DataSourceRows>;
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CtxErrorType {
     /// Division by 0 error
     Div0,
@@ -41,6 +50,8 @@ pub enum CtxDataType {
     DateTime(f64),
     Error(CtxErrorType),
     Empty,
+    List(Vec<CtxDataType>),
+    Map(IndexMap<String, CtxDataType>),
 }
 
 impl Serialize for CtxDataType {
@@ -50,7 +61,11 @@ impl Serialize for CtxDataType {
     {
         match self {
             Self::Int(value) => serializer.serialize_i64(*value),
-            Self::Float(value) | Self::DateTime(value) => serializer.serialize_f64(*value),
+            Self::Float(value) => serializer.serialize_f64(*value),
+            Self::DateTime(value) => match excel_serial_to_datetime(*value) {
+                Some(datetime) => serializer.serialize_str(&iso_8601(&datetime)),
+                None => serializer.serialize_f64(*value),
+            },
             Self::String(value) => serializer.serialize_str(value),
             Self::Bool(value) => serializer.serialize_bool(*value),
             Self::Error(value) => {
@@ -67,63 +82,130 @@ impl Serialize for CtxDataType {
                 serializer.serialize_str(s)
             }
             Self::Empty => serializer.serialize_unit(),
+            Self::List(values) => serializer.collect_seq(values),
+            Self::Map(values) => serializer.collect_map(values),
         }
     }
 }
 
-#[derive(Debug, Default)]
+impl From<serde_json::Value> for CtxDataType {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Self::Empty,
+            serde_json::Value::Bool(b) => Self::Bool(b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Self::Int(i),
+                None => Self::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Self::String(s),
+            serde_json::Value::Array(values) => {
+                Self::List(values.into_iter().map(Self::from).collect())
+            }
+            serde_json::Value::Object(values) => Self::Map(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// One file or sheet in a `MultiSourceReader`
+pub struct SourcePart {
+    /// File name, and sheet name for Excel files, used in messages
+    pub name: String,
+    /// Sheet name for Excel files
+    pub sheet: Option<String>,
+    pub reader: Box<dyn DataSourceReader>,
+}
+
 pub struct MultiSourceReader {
-    file_paths: Vec<PathBuf>,
-    delimiter: char,
+    parts: Vec<SourcePart>,
+    combine: Combine,
 }
 
 impl MultiSourceReader {
     pub fn new(file_names: Vec<&str>, relative_root: &Path, delimiter: Option<char>) -> Self {
-        Self {
-            file_paths: file_names.iter().map(|f| relative_root.join(f)).collect(),
-            delimiter: delimiter.unwrap_or(';'),
-        }
+        let parts = file_names
+            .iter()
+            .map(|file_name| SourcePart {
+                name: file_name_of(&relative_root.join(file_name)),
+                sheet: None,
+                reader: Box::new(CsvSourceReader::new(file_name, relative_root, delimiter)),
+            })
+            .collect();
+        Self::with_parts(parts, Combine::Merge)
+    }
+
+    pub fn with_parts(parts: Vec<SourcePart>, combine: Combine) -> Self {
+        Self { parts, combine }
     }
 }
 
+fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
 impl DataSourceReader for MultiSourceReader {
     fn read(&self) -> Result<DataSourceRows> {
         let mut rows = IndexMap::new();
-        if let Some((first, rest)) = self.file_paths.split_first() {
-            let first_reader = CsvSourceReader {
-                file_path: first.to_owned(),
-                delimiter: self.delimiter,
-            };
-            rows = first_reader
-                .read()
-                .with_context(|| format!("Problem reading source file '{}'", first.display()))?;
-
-            for file_path in rest {
-                let reader = CsvSourceReader {
-                    file_path: file_path.to_owned(),
-                    delimiter: self.delimiter,
-                };
-                let new_rows = reader.read().with_context(|| {
-                    format!("Problem reading source file '{}'", file_path.display())
-                })?;
-
-                for key in rows.keys() {
-                    if !new_rows.contains_key(key) {
-                        anyhow::bail!(
-                            "Key '{}' from '{}' is missing in '{}'",
-                            key,
-                            first.file_name().unwrap().to_string_lossy(),
-                            file_path.file_name().unwrap().to_string_lossy()
-                        );
+        let Some((first, rest)) = self.parts.split_first() else {
+            return Ok(rows);
+        };
+        match self.combine {
+            Combine::Merge => {
+                rows = first
+                    .reader
+                    .read()
+                    .with_context(|| format!("Problem reading source file '{}'", first.name))?;
+
+                for part in rest {
+                    let new_rows = part
+                        .reader
+                        .read()
+                        .with_context(|| format!("Problem reading source file '{}'", part.name))?;
+
+                    for key in rows.keys() {
+                        if !new_rows.contains_key(key) {
+                            anyhow::bail!(
+                                "Key '{}' from '{}' is missing in '{}'",
+                                key,
+                                first.name,
+                                part.name
+                            );
+                        }
                     }
-                }
 
-                for (key, values) in new_rows {
-                    if let Some(row) = rows.get_mut(&key) {
-                        for (col, value) in values {
-                            row.insert(col, value);
+                    for (key, values) in new_rows {
+                        if let Some(row) = rows.get_mut(&key) {
+                            for (col, value) in values {
+                                // Rows keep where they were first read from
+                                if col != META_COLUMN {
+                                    row.insert(col, value);
+                                }
+                            }
+                        } // TODO: warn if first csv does not contain key?
+                    }
+                }
+            }
+            Combine::Concat => {
+                for part in &self.parts {
+                    let new_rows = part
+                        .reader
+                        .read()
+                        .with_context(|| format!("Problem reading source file '{}'", part.name))?;
+                    for (key, mut values) in new_rows {
+                        if let Some(sheet) = &part.sheet {
+                            values.insert("_sheet".to_string(), CtxDataType::String(sheet.clone()));
                         }
-                    } // TODO: warn if first csv does not contain key?
+                        if rows.contains_key(&key) {
+                            bail!("Duplicate key '{}' in '{}'", key, part.name);
+                        }
+                        rows.insert(key, values);
+                    }
                 }
             }
         }
@@ -131,10 +213,391 @@ impl DataSourceReader for MultiSourceReader {
     }
 }
 
+/// Where the table is found in a source file and which columns identify its rows
+#[derive(Clone, Debug, Default)]
+pub struct TableLayout {
+    /// Columns that make up the row key. The first column is used if empty
+    pub key: Vec<String>,
+    /// Separator between the values of a composite key, defaults to "_"
+    pub key_separator: Option<String>,
+    /// 1-based row number of the header row, defaults to the first row
+    pub header_row: Option<usize>,
+    /// 1-based row number of the first data row, defaults to the row after the header row
+    pub data_start_row: Option<usize>,
+    /// Excel cell range like C3:H200 or the name of a named range
+    pub range: Option<String>,
+    /// Declared types and constraints for columns
+    pub columns: BTreeMap<String, Column>,
+}
+
+impl TableLayout {
+    fn check_key_columns<'a>(
+        &self,
+        mut headers: impl Iterator<Item = &'a str> + Clone,
+    ) -> Result<()> {
+        for column in &self.key {
+            if !headers.clone().any(|header| header == column) {
+                bail!("Key column '{column}' not found");
+            }
+        }
+        headers.next().context("No columns found")?;
+        Ok(())
+    }
+
+    /// The key for a row, where `first` is the value in the first column
+    fn row_key(
+        &self,
+        first: Option<&CtxDataType>,
+        row: &HashMap<String, CtxDataType>,
+        row_number: usize,
+    ) -> Result<String> {
+        if self.key.is_empty() {
+            return match first {
+                Some(CtxDataType::String(s)) if !s.trim().is_empty() => Ok(s.clone()),
+                _ => bail!("First column must contain strings only"),
+            };
+        }
+        let values = self
+            .key
+            .iter()
+            .map(|column| match row.get(column) {
+                Some(CtxDataType::String(s)) if !s.trim().is_empty() => Ok(s.clone()),
+                Some(CtxDataType::Int(i)) => Ok(i.to_string()),
+                Some(CtxDataType::Float(f)) => Ok(f.to_string()),
+                _ => bail!("Row {row_number} has no value in key column '{column}'"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(values.join(self.key_separator.as_deref().unwrap_or("_")))
+    }
+
+    /// Whether values in `column` are converted by the column schema and should be kept as text
+    fn is_typed(&self, column: &str) -> bool {
+        self.columns
+            .get(column)
+            .is_some_and(|column| column.kind.is_some())
+    }
+
+    fn column_checker(&self, location: String) -> Result<ColumnChecker<'_>> {
+        let mut rules = Vec::new();
+        for (name, column) in &self.columns {
+            let pattern = column
+                .pattern
+                .as_ref()
+                .map(|pattern| Regex::new(&format!("^(?:{pattern})$")))
+                .transpose()
+                .with_context(|| format!("Invalid pattern for column '{name}'"))?;
+            let allowed = column
+                .allowed
+                .iter()
+                .flatten()
+                .map(|value| display_value(&value.clone().into()))
+                .collect();
+            rules.push(ColumnRule {
+                name,
+                column,
+                default: column.default.clone().map(CtxDataType::from),
+                allowed,
+                pattern,
+            });
+        }
+        Ok(ColumnChecker {
+            location,
+            rules,
+            violations: Vec::new(),
+        })
+    }
+}
+
+/// Convert `value` to `kind`. Returns None if the value cannot be represented as that type.
+/// `format` is the strftime format of text in datetime columns, which defaults to ISO-8601.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+pub fn coerce(value: &CtxDataType, kind: ColumnType, format: Option<&str>) -> Option<CtxDataType> {
+    let parse_float = |s: &str| s.trim().replace(',', ".").parse::<f64>().ok();
+    match (kind, value) {
+        (_, CtxDataType::Empty) => Some(CtxDataType::Empty),
+        (ColumnType::String, CtxDataType::String(_)) => Some(value.clone()),
+        (
+            ColumnType::String,
+            CtxDataType::Int(_)
+            | CtxDataType::Float(_)
+            | CtxDataType::Bool(_)
+            | CtxDataType::DateTime(_),
+        ) => Some(CtxDataType::String(display_value(value))),
+        (ColumnType::DateTime, CtxDataType::DateTime(_)) => Some(value.clone()),
+        (ColumnType::DateTime, CtxDataType::String(s)) => parse_datetime(s.trim(), format)
+            .map(|datetime| CtxDataType::DateTime(datetime_to_excel_serial(&datetime))),
+        (ColumnType::Int, CtxDataType::Int(_)) => Some(value.clone()),
+        (ColumnType::Int, CtxDataType::Float(f)) if f.fract() == 0.0 => {
+            Some(CtxDataType::Int(*f as i64))
+        }
+        (ColumnType::Int, CtxDataType::String(s)) => match s.trim().parse::<i64>() {
+            Ok(i) => Some(CtxDataType::Int(i)),
+            Err(_) => parse_float(s)
+                .filter(|f| f.fract() == 0.0)
+                .map(|f| CtxDataType::Int(f as i64)),
+        },
+        (ColumnType::Float, CtxDataType::Float(_)) => Some(value.clone()),
+        (ColumnType::Float, CtxDataType::Int(i)) => Some(CtxDataType::Float(*i as f64)),
+        (ColumnType::Float, CtxDataType::String(s)) => parse_float(s).map(CtxDataType::Float),
+        (ColumnType::Bool, CtxDataType::Bool(_)) => Some(value.clone()),
+        (ColumnType::Bool, CtxDataType::Int(0)) => Some(CtxDataType::Bool(false)),
+        (ColumnType::Bool, CtxDataType::Int(1)) => Some(CtxDataType::Bool(true)),
+        (ColumnType::Bool, CtxDataType::String(s)) => {
+            match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Some(CtxDataType::Bool(true)),
+                "false" | "no" | "off" | "0" => Some(CtxDataType::Bool(false)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parse a date or date and time with a strftime `format`, or as ISO-8601 if no format is given
+pub fn parse_datetime(s: &str, format: Option<&str>) -> Option<NaiveDateTime> {
+    let formats = match format {
+        Some(format) => vec![format],
+        None => vec!["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%d"],
+    };
+    formats.into_iter().find_map(|format| {
+        NaiveDateTime::parse_from_str(s, format).ok().or_else(|| {
+            NaiveDate::parse_from_str(s, format)
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+    })
+}
+
+/// Excel stores dates as days since 1899-12-30, with the time of day as the fraction
+fn excel_epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1899, 12, 30)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .unwrap_or_default()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub fn excel_serial_to_datetime(serial: f64) -> Option<NaiveDateTime> {
+    let seconds = (serial * 86_400.0).round() as i64;
+    excel_epoch().checked_add_signed(TimeDelta::try_seconds(seconds)?)
+}
+
+/// Days in an ISO 8601 duration like `PT07H30M00S` or `P1DT06H00M00S`, which is how .ods
+/// files store time cells. Returned as a fraction of days, like an Excel time cell.
+fn parse_duration_iso(s: &str) -> Option<f64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let s = s.strip_prefix('P')?;
+    let (date, time) = s.split_once('T').unwrap_or((s, ""));
+    let mut seconds = 0.0;
+    for (part, units) in [
+        (date, [('D', 86_400.0)].as_slice()),
+        (time, &[('H', 3_600.0), ('M', 60.0), ('S', 1.0)]),
+    ] {
+        let mut rest = part;
+        for (unit, factor) in units {
+            if let Some((number, tail)) = rest.split_once(*unit) {
+                seconds += number.parse::<f64>().ok()? * factor;
+                rest = tail;
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(if negative { -seconds } else { seconds } / 86_400.0)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn datetime_to_excel_serial(datetime: &NaiveDateTime) -> f64 {
+    (*datetime - excel_epoch()).num_seconds() as f64 / 86_400.0
+}
+
+/// A date as YYYY-MM-DD, or a date and time as YYYY-MM-DDTHH:MM:SS
+pub fn iso_8601(datetime: &NaiveDateTime) -> String {
+    if datetime.time() == NaiveTime::MIN {
+        datetime.format("%Y-%m-%d").to_string()
+    } else {
+        datetime.format("%Y-%m-%dT%H:%M:%S").to_string()
+    }
+}
+
+/// A value as it is shown in messages and compared with allowed values and patterns
+pub fn display_value(value: &CtxDataType) -> String {
+    match value {
+        CtxDataType::String(s) => s.clone(),
+        CtxDataType::Int(i) => i.to_string(),
+        CtxDataType::Float(f) => f.to_string(),
+        CtxDataType::Bool(b) => b.to_string(),
+        CtxDataType::Empty => String::new(),
+        other => match serde_json::to_value(other) {
+            Ok(serde_json::Value::String(s)) => s,
+            Ok(value) => value.to_string(),
+            Err(_) => String::new(),
+        },
+    }
+}
+
+struct ColumnRule<'a> {
+    name: &'a str,
+    column: &'a Column,
+    default: Option<CtxDataType>,
+    allowed: Vec<String>,
+    pattern: Option<Regex>,
+}
+
+/// Applies the column schema of a source to each row and collects every violation, so they
+/// can all be reported at once when the file has been read
+struct ColumnChecker<'a> {
+    /// File, and sheet for Excel files, used in messages
+    location: String,
+    rules: Vec<ColumnRule<'a>>,
+    violations: Vec<String>,
+}
+
+impl ColumnChecker<'_> {
+    /// Fail if a declared column is not among the columns in the file, e.g. because of a typo
+    fn check_headers<'h>(&self, headers: impl Iterator<Item = &'h str> + Clone) -> Result<()> {
+        for rule in &self.rules {
+            if !headers.clone().any(|header| header == rule.name) {
+                bail!("Column '{}' not found in {}", rule.name, self.location);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in defaults and convert the values in `row` to their declared types
+    fn check(&mut self, row: &mut HashMap<String, CtxDataType>, row_number: usize) {
+        for rule in &self.rules {
+            let mut violation = |message: String| {
+                self.violations.push(format!(
+                    "{}, row {row_number}, column '{}': {message}",
+                    self.location, rule.name
+                ));
+            };
+            let mut value = row.get(rule.name).cloned().unwrap_or(CtxDataType::Empty);
+            if matches!(&value, CtxDataType::String(s) if s.trim().is_empty()) {
+                value = CtxDataType::Empty;
+            }
+            if value == CtxDataType::Empty {
+                match &rule.default {
+                    Some(default) => value = default.clone(),
+                    None if rule.column.required => {
+                        violation("missing required value".to_string());
+                        continue;
+                    }
+                    None => (),
+                }
+            }
+            if value != CtxDataType::Empty {
+                if let Some(kind) = rule.column.kind {
+                    match coerce(&value, kind, rule.column.format.as_deref()) {
+                        Some(coerced) => value = coerced,
+                        None => {
+                            violation(format!("'{}' is not a valid {kind}", display_value(&value)));
+                            continue;
+                        }
+                    }
+                }
+                let text = display_value(&value);
+                if !rule.allowed.is_empty() && !rule.allowed.contains(&text) {
+                    violation(format!(
+                        "'{text}' is not one of {}",
+                        rule.allowed.join(", ")
+                    ));
+                }
+                if let Some(pattern) = &rule.pattern
+                    && !pattern.is_match(&text)
+                {
+                    violation(format!(
+                        "'{text}' does not match pattern '{}'",
+                        rule.column.pattern.as_deref().unwrap_or_default()
+                    ));
+                }
+            }
+            row.insert(rule.name.to_string(), value);
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} invalid value(s):\n{}",
+            self.violations.len(),
+            self.violations.join("\n")
+        )
+    }
+
+    /// Fail with all violations found in the rows checked so far
+    fn finish(self) -> Result<()> {
+        if self.violations.is_empty() {
+            return Ok(());
+        }
+        bail!(self.summary())
+    }
+
+    /// `err` followed by the violations found before it, so that none are lost when reading
+    /// stops early
+    fn abort(&self, err: anyhow::Error) -> anyhow::Error {
+        if self.violations.is_empty() {
+            return err;
+        }
+        anyhow!("{err:#}\n{}", self.summary())
+    }
+}
+
+/// Add the row under `key`, with a `_meta` column that tells where the row was read from
+fn insert_row(
+    rows: &mut DataSourceRows,
+    key: String,
+    mut data: HashMap<String, CtxDataType>,
+    row_number: usize,
+    file: &str,
+    sheet: Option<&str>,
+) -> Result<()> {
+    if data.contains_key(META_COLUMN) {
+        bail!("Column '{META_COLUMN}' in {file} is reserved for row provenance, please rename it");
+    }
+    let mut meta = IndexMap::new();
+    meta.insert("file".to_string(), CtxDataType::String(file.to_string()));
+    if let Some(sheet) = sheet {
+        meta.insert("sheet".to_string(), CtxDataType::String(sheet.to_string()));
+    }
+    meta.insert(
+        "row".to_string(),
+        CtxDataType::Int(i64::try_from(row_number).unwrap_or(i64::MAX)),
+    );
+    meta.insert("key".to_string(), CtxDataType::String(key.clone()));
+    data.insert(META_COLUMN.to_string(), CtxDataType::Map(meta));
+    match rows.entry(key) {
+        indexmap::map::Entry::Occupied(entry) => {
+            bail!("Duplicate key '{}' in row {row_number}", entry.key())
+        }
+        indexmap::map::Entry::Vacant(entry) => {
+            entry.insert(data);
+        }
+    }
+    Ok(())
+}
+
+/// Add the source id first in the `_meta` column of each row
+pub fn set_meta_source(rows: &mut DataSourceRows, source_id: &str) {
+    for row in rows.values_mut() {
+        if let Some(CtxDataType::Map(meta)) = row.get_mut(META_COLUMN) {
+            meta.shift_insert(
+                0,
+                "source".to_string(),
+                CtxDataType::String(source_id.to_string()),
+            );
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CsvSourceReader {
     file_path: PathBuf,
     delimiter: char,
+    layout: TableLayout,
 }
 
 impl CsvSourceReader {
@@ -142,31 +605,88 @@ impl CsvSourceReader {
         Self {
             file_path: relative_root.join(file_name),
             delimiter: delimiter.unwrap_or(';'),
+            layout: TableLayout::default(),
         }
     }
+
+    pub fn with_layout(mut self, layout: TableLayout) -> Self {
+        self.layout = layout;
+        self
+    }
 }
 
 impl DataSourceReader for CsvSourceReader {
     fn read(&self) -> Result<DataSourceRows> {
+        let content = fs::read(&self.file_path)?;
+        // Lines before the header row and between the header row and the first data row are
+        // removed before parsing, so they may contain anything
+        let lines: Vec<&[u8]> = content.split_inclusive(|byte| *byte == b'\n').collect();
+        let header_row = self.layout.header_row.unwrap_or(1);
+        let data_start_row = self.layout.data_start_row.unwrap_or(header_row + 1);
+        let Some(header_line) = header_row.checked_sub(1).and_then(|i| lines.get(i)) else {
+            bail!("Header row {header_row} is beyond the end of the file");
+        };
+        let mut table = header_line.to_vec();
+        if !table.ends_with(b"\n") {
+            table.push(b'\n');
+        }
+        // Where each line starts in the table and its line number in the file
+        let mut line_starts = vec![(0, header_row)];
+        for (i, line) in lines
+            .iter()
+            .enumerate()
+            .skip(data_start_row.saturating_sub(1))
+        {
+            line_starts.push((table.len(), i + 1));
+            table.extend_from_slice(line);
+        }
+
         let mut reader = csv::ReaderBuilder::new()
             .delimiter(self.delimiter as u8)
             .flexible(false)
             .comment(Some(b'#'))
             .trim(Trim::All)
-            .from_path(&self.file_path)?;
+            .from_reader(table.as_slice());
 
         let headers = reader.headers()?.clone();
+        self.layout.check_key_columns(headers.iter())?;
 
+        let file = file_name_of(&self.file_path);
+        let mut checker = self.layout.column_checker(file.clone())?;
+        checker.check_headers(headers.iter())?;
         let mut rows = IndexMap::new();
 
-        for record_result in reader.records() {
+        for (i, record_result) in reader.records().enumerate() {
             let record = record_result.with_context(|| "Error reading CSV record")?;
+            // Rows with only empty fields are skipped, like empty rows in spreadsheets
+            if record.iter().all(str::is_empty) {
+                continue;
+            }
+            let row_number = record
+                .position()
+                .and_then(|position| usize::try_from(position.byte()).ok())
+                .map_or(data_start_row + i, |byte| {
+                    let mut index = line_starts
+                        .partition_point(|(start, _)| *start <= byte)
+                        .saturating_sub(1);
+                    // The position of a record after comment or blank lines is where those start
+                    while index + 1 < line_starts.len()
+                        && matches!(table.get(line_starts[index].0), Some(b'#' | b'\n' | b'\r'))
+                    {
+                        index += 1;
+                    }
+                    line_starts[index].1
+                });
 
             let mut data = HashMap::new();
             for (i, value) in record.iter().enumerate() {
                 if let Some(header_field) = headers.get(i) {
                     let converted_value = match value {
                         "" => CtxDataType::Empty,
+                        // Converted to the declared type by the column checker
+                        v if self.layout.is_typed(header_field) => {
+                            CtxDataType::String(v.to_string())
+                        }
                         v if v.parse::<i64>().is_ok() => {
                             if v.starts_with('0') && v != "0" {
                                 CtxDataType::String(value.to_string())
@@ -184,28 +704,29 @@ impl DataSourceReader for CsvSourceReader {
                     data.insert(header_field.to_string(), converted_value);
                 }
             }
-            let key = record[0].to_string();
-            rows.insert(key, data);
-        }
-        let first_column_name = headers.get(0).map(ToString::to_string).unwrap_or_default();
-
-        if rows.iter().any(|(column_name, data)| {
-            column_name != &first_column_name
-                && match data.get(&first_column_name) {
-                    Some(CtxDataType::String(val)) => val.trim().is_empty(),
-                    _ => true,
-                }
-        }) {
-            bail!("First column must contain strings only");
+            checker.check(&mut data, row_number);
+            let first = headers.get(0).and_then(|header| data.get(header));
+            let key = self
+                .layout
+                .row_key(first, &data, row_number)
+                .map_err(|err| checker.abort(err))?;
+            insert_row(&mut rows, key, data, row_number, &file, None)
+                .map_err(|err| checker.abort(err))?;
         }
+        checker.finish()?;
         Ok(rows)
     }
 }
 
+/// File extensions read by `ExcelSourceReader`
+pub const SPREADSHEET_EXTENSIONS: [&str; 4] = ["xlsx", "xlsm", "xls", "ods"];
+
 #[derive(Debug)]
 pub struct ExcelSourceReader {
     file_path: PathBuf,
     sheet: Option<String>,
+    layout: TableLayout,
+    on_cell_error: OnCellError,
 }
 
 impl ExcelSourceReader {
@@ -213,78 +734,477 @@ impl ExcelSourceReader {
         Self {
             file_path: relative_root.join(file_name),
             sheet: sheet.map(std::borrow::ToOwned::to_owned),
+            layout: TableLayout::default(),
+            on_cell_error: OnCellError::default(),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: TableLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_on_cell_error(mut self, on_cell_error: OnCellError) -> Self {
+        self.on_cell_error = on_cell_error;
+        self
+    }
+
+    /// Names of the sheets in the workbook that match `patterns`, in the order of the patterns
+    /// and then the workbook. Patterns may contain wildcards like `Wells_*`. Fails if a pattern
+    /// matches no sheet.
+    pub fn matching_sheets(
+        file_name: &str,
+        relative_root: &Path,
+        patterns: &[&str],
+    ) -> Result<Vec<String>> {
+        let file_path = relative_root.join(file_name);
+        let workbook = open_workbook_auto(&file_path)
+            .with_context(|| format!("Problem reading source file '{}'", file_path.display()))?;
+        let sheet_names = workbook.sheet_names();
+
+        let mut matches = Vec::new();
+        for pattern in patterns {
+            let glob_pattern = glob::Pattern::new(pattern)
+                .with_context(|| format!("Invalid sheet pattern '{pattern}'"))?;
+            let mut found = false;
+            for name in &sheet_names {
+                if glob_pattern.matches(name) {
+                    found = true;
+                    if !matches.contains(name) {
+                        matches.push(name.clone());
+                    }
+                }
+            }
+            if !found {
+                bail!(
+                    "No sheet matching '{}' in '{}'",
+                    pattern,
+                    file_name_of(&file_path)
+                );
+            }
+        }
+        Ok(matches)
+    }
+
+    /// The sheet to read and, if `range` is given, the first and last cell of the table
+    fn sheet_and_cells<R: std::io::Read + std::io::Seek>(
+        &self,
+        workbook: &Sheets<R>,
+    ) -> Result<(String, Option<CellRange>)> {
+        let Some(range) = &self.layout.range else {
+            let sheet = self.sheet.clone().context("No sheet specified")?;
+            return Ok((sheet, None));
+        };
+        if let Some(cells) = parse_cell_range(range) {
+            let sheet = self.sheet.clone().context("No sheet specified")?;
+            return Ok((sheet, Some(cells)));
+        }
+        let (_, formula) = workbook
+            .defined_names()
+            .iter()
+            .find(|(name, _)| name == range)
+            .with_context(|| format!("'{range}' is neither a cell range nor a named range"))?;
+        let (sheet, cells) = formula
+            .rsplit_once('!')
+            .and_then(|(sheet, cells)| Some((sheet, parse_cell_range(cells)?)))
+            .with_context(|| format!("Named range '{range}' refers to '{formula}'"))?;
+        let sheet = sheet
+            .strip_prefix('\'')
+            .and_then(|s| s.strip_suffix('\''))
+            .map_or(sheet.to_string(), |s| s.replace("''", "'"));
+        Ok((sheet, Some(cells)))
+    }
+}
+
+/// First and last cell of a range as 0-based (row, column)
+type CellRange = ((u32, u32), (u32, u32));
+
+static CELL_RANGE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\$?([A-Za-z]{1,3})\$?(\d+):\$?([A-Za-z]{1,3})\$?(\d+)$").unwrap()
+});
+
+/// Parse a cell range like `C3:H200` or `$C$3:$H$200`
+fn parse_cell_range(range: &str) -> Option<CellRange> {
+    let caps = CELL_RANGE_RE.captures(range.trim())?;
+    let column = |letters: &str| {
+        letters
+            .to_ascii_uppercase()
+            .bytes()
+            .fold(0, |acc, b| acc * 26 + u32::from(b - b'A') + 1)
+            - 1
+    };
+    let row = |digits: &str| digits.parse::<u32>().ok()?.checked_sub(1);
+    Some((
+        (row(&caps[2])?, column(&caps[1])),
+        (row(&caps[4])?, column(&caps[3])),
+    ))
+}
+
+/// Column letters like `A` or `AB` for a 0-based column number
+fn column_name(column: u32) -> String {
+    let mut name = Vec::new();
+    let mut n = column + 1;
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        name.push(b'A' + rem as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8_lossy(&name).to_string()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn cell_to_ctx(cell: &Data) -> CtxDataType {
+    match cell.clone() {
+        Data::Int(i) => CtxDataType::Int(i),
+        Data::Float(f) => {
+            if (f - f.floor()).abs() < f64::EPSILON {
+                CtxDataType::Int(f as i64)
+            } else {
+                CtxDataType::Float(f)
+            }
         }
+        Data::String(s) => CtxDataType::String(s),
+        Data::Bool(b) => CtxDataType::Bool(b),
+        Data::DateTime(d) => CtxDataType::DateTime(d.as_f64()),
+        Data::DateTimeIso(s) => match parse_datetime(&s, None) {
+            Some(datetime) => CtxDataType::DateTime(datetime_to_excel_serial(&datetime)),
+            None => CtxDataType::String(s),
+        },
+        Data::DurationIso(s) => match parse_duration_iso(&s) {
+            Some(days) => CtxDataType::DateTime(days),
+            None => CtxDataType::String(s),
+        },
+        Data::Empty => CtxDataType::Empty,
+        Data::Error(e) => match e {
+            CellErrorType::Div0 => CtxDataType::Error(CtxErrorType::Div0),
+            CellErrorType::NA => CtxDataType::Error(CtxErrorType::NA),
+            CellErrorType::Name => CtxDataType::Error(CtxErrorType::Name),
+            CellErrorType::Null => CtxDataType::Error(CtxErrorType::Null),
+            CellErrorType::Num => CtxDataType::Error(CtxErrorType::Num),
+            CellErrorType::Ref => CtxDataType::Error(CtxErrorType::Ref),
+            CellErrorType::Value => CtxDataType::Error(CtxErrorType::Value),
+            CellErrorType::GettingData => CtxDataType::Error(CtxErrorType::GettingData),
+        },
     }
 }
 
 impl DataSourceReader for ExcelSourceReader {
     #[allow(clippy::missing_errors_doc)]
-    #[allow(clippy::missing_panics_doc)]
-    #[allow(clippy::cast_possible_truncation)]
     fn read(&self) -> Result<DataSourceRows> {
-        let mut workbook: Xlsx<_> = open_workbook(&self.file_path)?;
-        let sheet = self.sheet.as_ref().unwrap();
+        let mut workbook = open_workbook_auto(&self.file_path)?;
+        let (sheet, cells) = self.sheet_and_cells(&workbook)?;
 
-        let range = workbook.worksheet_range(sheet)?;
+        let mut range = workbook.worksheet_range(&sheet)?;
+        if let Some((start, end)) = cells {
+            range = range.range(start, end);
+        }
 
-        if range
-            .rows()
-            .skip(1)
-            .any(|row| row[0].get_string().is_none())
-        {
-            bail!("First column must contain strings only");
-        }
-
-        let row_headers = range.rows().next().unwrap();
-        let data = range
-            .rows()
-            .skip(1)
-            .map(|row| {
-                let key = row[0].get_string().unwrap().to_string();
-                let values = row_headers
-                    .iter()
-                    .zip(row.iter())
-                    .map(|(header, cell)| {
-                        let header_str = header.get_string().unwrap().to_string();
-                        let value = match cell.clone() {
-                            Data::Int(i) => CtxDataType::Int(i),
-                            Data::Float(f) => {
-                                if (f - f.floor()).abs() < f64::EPSILON {
-                                    CtxDataType::Int(f as i64)
-                                } else {
-                                    CtxDataType::Float(f)
-                                }
-                            }
-                            Data::String(s) => CtxDataType::String(s),
-                            Data::Bool(b) => CtxDataType::Bool(b),
-                            Data::DateTime(d) => CtxDataType::DateTime(d.as_f64()),
-                            Data::Empty => CtxDataType::Empty,
-                            Data::Error(e) => match e {
-                                CellErrorType::Div0 => CtxDataType::Error(CtxErrorType::Div0),
-                                CellErrorType::NA => CtxDataType::Error(CtxErrorType::NA),
-                                CellErrorType::Name => CtxDataType::Error(CtxErrorType::Name),
-                                CellErrorType::Null => CtxDataType::Error(CtxErrorType::Null),
-                                CellErrorType::Num => CtxDataType::Error(CtxErrorType::Num),
-                                CellErrorType::Ref => CtxDataType::Error(CtxErrorType::Ref),
-                                CellErrorType::Value => CtxDataType::Error(CtxErrorType::Value),
-                                CellErrorType::GettingData => {
-                                    CtxDataType::Error(CtxErrorType::GettingData)
-                                }
-                            },
-                            _ => {
-                                panic!("Unhandled datatype for {cell})"); // Should never happen
-                            }
-                        };
-                        (header_str, value)
-                    })
-                    .collect::<HashMap<String, CtxDataType>>();
-                (key, values)
+        // Row numbers are 0-based here, while the layout uses the row numbers shown in Excel
+        let first_row = range.start().map_or(0, |(row, _)| row as usize);
+        let header_row = self
+            .layout
+            .header_row
+            .map_or(first_row, |row| row.saturating_sub(1));
+        let data_start_row = self
+            .layout
+            .data_start_row
+            .map_or(header_row + 1, |row| row.saturating_sub(1));
+
+        let row_headers = header_row
+            .checked_sub(first_row)
+            .and_then(|i| range.rows().nth(i))
+            .with_context(|| {
+                format!(
+                    "Header row {} is outside the data in sheet '{sheet}'",
+                    header_row + 1
+                )
+            })?;
+        let row_headers: Vec<Option<String>> = row_headers
+            .iter()
+            .map(|header| match header {
+                Data::Empty => None,
+                Data::String(s) => Some(s.clone()),
+                other => Some(other.to_string()),
             })
-            .collect::<DataSourceRows>();
+            .collect();
+        self.layout
+            .check_key_columns(row_headers.iter().flatten().map(String::as_str))?;
+
+        let file = file_name_of(&self.file_path);
+        let mut checker = self.layout.column_checker(format!("{file}[{sheet}]"))?;
+        checker.check_headers(row_headers.iter().flatten().map(String::as_str))?;
+        let first_column = range.start().map_or(0, |(_, column)| column);
+        let mut error_cells = Vec::new();
+        let mut data = IndexMap::new();
+        for (i, row) in range.rows().enumerate() {
+            let row_number = first_row + i + 1;
+            if row_number <= data_start_row || row.iter().all(|cell| *cell == Data::Empty) {
+                continue;
+            }
+            let mut values = HashMap::new();
+            for (j, (header, cell)) in row_headers.iter().zip(row.iter()).enumerate() {
+                let Some(header) = header else {
+                    continue;
+                };
+                let mut value = cell_to_ctx(cell);
+                if let CtxDataType::Error(_) = value {
+                    let message = format!(
+                        "sheet '{sheet}', cell {}{row_number}, column '{header}': {}",
+                        column_name(first_column + j as u32),
+                        display_value(&value)
+                    );
+                    match self.on_cell_error {
+                        OnCellError::Fail => error_cells.push(message),
+                        OnCellError::Warn => eprintln!(
+                            "Warning: error cell in '{}' {message}",
+                            file_name_of(&self.file_path)
+                        ),
+                        OnCellError::Empty => value = CtxDataType::Empty,
+                    }
+                }
+                values.insert(header.clone(), value);
+            }
+            checker.check(&mut values, row_number);
+            let first = match row_headers.first() {
+                Some(Some(header)) => values.get(header).cloned(),
+                _ => row.first().map(cell_to_ctx),
+            };
+            let key = self
+                .layout
+                .row_key(first.as_ref(), &values, row_number)
+                .map_err(|err| checker.abort(err))?;
+            insert_row(&mut data, key, values, row_number, &file, Some(&sheet))
+                .map_err(|err| checker.abort(err))?;
+        }
+        if !error_cells.is_empty() {
+            return Err(checker.abort(anyhow!(
+                "{} error cell(s) in '{file}':\n{}",
+                error_cells.len(),
+                error_cells.join("\n")
+            )));
+        }
+        checker.finish()?;
         Ok(data)
     }
 }
 
+#[derive(Debug)]
+pub struct JsonSourceReader {
+    file_path: PathBuf,
+    layout: TableLayout,
+}
+
+impl JsonSourceReader {
+    pub fn new(file_name: &str, relative_root: &Path) -> Self {
+        Self {
+            file_path: relative_root.join(file_name),
+            layout: TableLayout::default(),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: TableLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+impl DataSourceReader for JsonSourceReader {
+    fn read(&self) -> Result<DataSourceRows> {
+        let content = fs::read_to_string(&self.file_path)?;
+        let value: serde_json::Value = serde_json::from_str(&content)?;
+        rows_from_structured(value, &self.layout, file_name_of(&self.file_path))
+    }
+}
+
+#[derive(Debug)]
+pub struct YamlSourceReader {
+    file_path: PathBuf,
+    layout: TableLayout,
+}
+
+impl YamlSourceReader {
+    pub fn new(file_name: &str, relative_root: &Path) -> Self {
+        Self {
+            file_path: relative_root.join(file_name),
+            layout: TableLayout::default(),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: TableLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+impl DataSourceReader for YamlSourceReader {
+    fn read(&self) -> Result<DataSourceRows> {
+        let content = fs::read_to_string(&self.file_path)?;
+        let value: serde_json::Value = serde_yaml::from_str(&content)?;
+        rows_from_structured(value, &self.layout, file_name_of(&self.file_path))
+    }
+}
+
+#[derive(Debug)]
+pub struct SqliteSourceReader {
+    file_path: PathBuf,
+    query: String,
+    layout: TableLayout,
+}
+
+impl SqliteSourceReader {
+    pub fn new(file_name: &str, relative_root: &Path, query: &str) -> Self {
+        Self {
+            file_path: relative_root.join(file_name),
+            query: query.to_string(),
+            layout: TableLayout::default(),
+        }
+    }
+
+    pub fn with_layout(mut self, layout: TableLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+impl DataSourceReader for SqliteSourceReader {
+    fn read(&self) -> Result<DataSourceRows> {
+        let conn = rusqlite::Connection::open_with_flags(
+            &self.file_path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        let mut stmt = conn
+            .prepare(&self.query)
+            .with_context(|| format!("Invalid query '{}'", self.query))?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        self.layout
+            .check_key_columns(columns.iter().map(String::as_str))
+            .with_context(|| format!("Query '{}'", self.query))?;
+
+        let file = file_name_of(&self.file_path);
+        let mut checker = self.layout.column_checker(file.clone())?;
+        checker.check_headers(columns.iter().map(String::as_str))?;
+        let mut rows = IndexMap::new();
+        let mut result = stmt.query([])?;
+        let mut row_number = 0;
+        while let Some(row) = result.next()? {
+            row_number += 1;
+            let mut data = HashMap::new();
+            for (i, column) in columns.iter().enumerate() {
+                let value = match row.get_ref(i)? {
+                    rusqlite::types::ValueRef::Null => CtxDataType::Empty,
+                    rusqlite::types::ValueRef::Integer(i) => CtxDataType::Int(i),
+                    rusqlite::types::ValueRef::Real(f) => CtxDataType::Float(f),
+                    rusqlite::types::ValueRef::Text(t) => {
+                        CtxDataType::String(String::from_utf8_lossy(t).to_string())
+                    }
+                    rusqlite::types::ValueRef::Blob(_) => {
+                        bail!("Unsupported BLOB value in column '{column}'")
+                    }
+                };
+                data.insert(column.clone(), value);
+            }
+            checker.check(&mut data, row_number);
+            let key = self
+                .layout
+                .row_key(data.get(&columns[0]), &data, row_number)
+                .map_err(|err| checker.abort(err))?;
+            insert_row(&mut rows, key, data, row_number, &file, None)
+                .map_err(|err| checker.abort(err))?;
+        }
+        checker.finish()?;
+        Ok(rows)
+    }
+}
+
+/// Convert the content of a .json or .yaml source to rows. The content is either a list of
+/// objects where the `key` fields hold the row key, or a map from row key to object. In the
+/// latter case the row key is also added to each row if a single `key` field is specified.
+fn rows_from_structured(
+    value: serde_json::Value,
+    layout: &TableLayout,
+    location: String,
+) -> Result<DataSourceRows> {
+    let to_row = |row_key: &str, row: serde_json::Value| -> Result<HashMap<String, CtxDataType>> {
+        let serde_json::Value::Object(row) = row else {
+            bail!("Row '{row_key}' is not an object");
+        };
+        Ok(row
+            .into_iter()
+            .map(|(column, value)| (column, CtxDataType::from(value)))
+            .collect())
+    };
+
+    let mut checker = layout.column_checker(location.clone())?;
+    let mut columns = HashSet::new();
+    let mut rows = DataSourceRows::new();
+    match value {
+        serde_json::Value::Array(items) => {
+            if layout.key.is_empty() {
+                bail!("Rows are given as a list, specify the field to use as row key with 'key'");
+            }
+            for (i, item) in items.into_iter().enumerate() {
+                let mut data = to_row(&format!("{}", i + 1), item)?;
+                columns.extend(data.keys().cloned());
+                checker.check(&mut data, i + 1);
+                let key = layout
+                    .row_key(None, &data, i + 1)
+                    .map_err(|err| checker.abort(err))?;
+                insert_row(&mut rows, key, data, i + 1, &location, None)
+                    .map_err(|err| checker.abort(err))?;
+            }
+        }
+        serde_json::Value::Object(items) => {
+            for (i, (row_key, item)) in items.into_iter().enumerate() {
+                let mut data = to_row(&row_key, item)?;
+                if let [key] = layout.key.as_slice() {
+                    data.entry(key.clone())
+                        .or_insert_with(|| CtxDataType::String(row_key.clone()));
+                }
+                columns.extend(data.keys().cloned());
+                checker.check(&mut data, i + 1);
+                insert_row(&mut rows, row_key, data, i + 1, &location, None)
+                    .map_err(|err| checker.abort(err))?;
+            }
+        }
+        _ => bail!("Expected a list of rows or a map from row key to row"),
+    }
+    // Fields may be left out of single rows, but a declared column must be in at least one
+    if !rows.is_empty() {
+        checker.check_headers(columns.iter().map(String::as_str))?;
+    }
+    checker.finish()?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod datetimetests {
+    use super::*;
+
+    #[test]
+    fn datetime_serializes_as_iso_8601() {
+        let to_json = |value: CtxDataType| serde_json::to_value(value).unwrap();
+        assert_eq!(to_json(CtxDataType::DateTime(45443.0)), "2024-05-31");
+        assert_eq!(
+            to_json(CtxDataType::DateTime(45443.604166666664)),
+            "2024-05-31T14:30:00"
+        );
+    }
+
+    #[test]
+    fn excel_serial_roundtrip() {
+        let datetime = parse_datetime("2024-05-31 14:30:00", None).unwrap();
+        let serial = datetime_to_excel_serial(&datetime);
+        assert!((serial - 45443.604166666664).abs() < 1e-9);
+        assert_eq!(excel_serial_to_datetime(serial), Some(datetime));
+        assert_eq!(parse_datetime("31/05/2024", None), None);
+        assert_eq!(
+            parse_datetime("31/05/2024", Some("%d/%m/%Y")).map(|d| iso_8601(&d)),
+            Some("2024-05-31".to_string())
+        );
+    }
+}
+
 #[cfg(test)]
 mod multisourcetests {
     use super::*;
@@ -571,13 +1491,126 @@ key2;value2a"#;
     }
 
     #[test]
-    fn csv_read_errors_on_first_column_not_string() {
-        let csv_content = r#"keys;text;float;int
-key1;value1;1.1;1
-2;value2;2.2;2"#;
-        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
-        write!(tmp_file, "{csv_content}").unwrap();
-
+    fn csv_row_numbers_count_comment_lines() {
+        let read = |csv_content: &str| {
+            let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+            write!(tmp_file, "{csv_content}").unwrap();
+            CsvSourceReader::new(
+                tmp_file.path().to_str().unwrap(),
+                std::path::Path::new(""),
+                Some(';'),
+            )
+            .with_layout(TableLayout {
+                key: vec!["tag".to_string()],
+                ..Default::default()
+            })
+            .read()
+        };
+        let message = read("tag;value\nA;1\n# Comment\n# Another\nB;2\nA;3\n")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Duplicate key 'A' in row 6"), "{message}");
+        let message = read("tag;value\n# Comment\nA;1\n;2\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("Row 4 has no value in key column 'tag'"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn csv_skips_empty_rows_like_spreadsheets() {
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "tag;value\nA;1\n;\n  ;  \n\nB;2\n").unwrap();
+        let data = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .read()
+        .unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["A", "B"]);
+        let Some(CtxDataType::Map(meta)) = data["B"].get(META_COLUMN) else {
+            panic!("missing {META_COLUMN}");
+        };
+        assert_eq!(meta.get("row"), Some(&CtxDataType::Int(6)));
+    }
+
+    #[test]
+    fn csv_reads_table_below_title_rows() {
+        let csv_content = r#"Tag list;exported 2024-01-01
+this line is not part of the table
+area;tag;unit
+-;-;-
+north;FI-101;m3/h
+# Comment
+south;FI-101;m3/h"#;
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{csv_content}").unwrap();
+
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .with_layout(TableLayout {
+            key: vec!["tag".to_string(), "area".to_string()],
+            header_row: Some(3),
+            data_start_row: Some(5),
+            ..Default::default()
+        });
+        let data = reader.read().unwrap();
+        assert_eq!(
+            data.keys().collect::<Vec<_>>(),
+            ["FI-101_north", "FI-101_south"]
+        );
+        assert_eq!(
+            data["FI-101_south"].get("unit"),
+            Some(&CtxDataType::String("m3/h".to_string()))
+        );
+        let Some(CtxDataType::Map(meta)) = data["FI-101_south"].get(META_COLUMN) else {
+            panic!("missing {META_COLUMN}");
+        };
+        assert_eq!(meta.get("row"), Some(&CtxDataType::Int(7)));
+        assert_eq!(
+            meta.get("key"),
+            Some(&CtxDataType::String("FI-101_south".to_string()))
+        );
+        assert_eq!(meta.get("sheet"), None);
+    }
+
+    #[test]
+    fn csv_read_errors_on_duplicate_key() {
+        let csv_content = r#"keys;text
+key1;value1
+key2;value2
+key1;value3"#;
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{csv_content}").unwrap();
+
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        );
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Duplicate key 'key1' in row 4")
+        );
+    }
+
+    #[test]
+    fn csv_read_errors_on_first_column_not_string() {
+        let csv_content = r#"keys;text;float;int
+key1;value1;1.1;1
+2;value2;2.2;2"#;
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{csv_content}").unwrap();
+
         let reader = CsvSourceReader::new(
             tmp_file.path().to_str().unwrap(),
             std::path::Path::new(""),
@@ -619,6 +1652,177 @@ key1;value1;1.1;1
                 .contains("First column must contain strings only")
         );
     }
+
+    fn columns_layout(yaml: &str) -> TableLayout {
+        TableLayout {
+            columns: serde_yaml::from_str(yaml).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn csv_coerces_declared_column_types() {
+        let csv_content = r#"keys;code;gain;enabled;unit
+key1;007;1,5;TRUE;
+key2;12;2;no;m3/h"#;
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{csv_content}").unwrap();
+
+        let layout = columns_layout(
+            r#"
+code: { type: string }
+gain: { type: float }
+enabled: { type: bool }
+unit: { default: kg/h }
+"#,
+        );
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .with_layout(layout);
+        let data = reader.read().unwrap();
+
+        let values = &data["key1"];
+        assert_eq!(values["code"], CtxDataType::String("007".to_string()));
+        assert_eq!(values["gain"], CtxDataType::Float(1.5));
+        assert_eq!(values["enabled"], CtxDataType::Bool(true));
+        assert_eq!(values["unit"], CtxDataType::String("kg/h".to_string()));
+
+        let values = &data["key2"];
+        assert_eq!(values["code"], CtxDataType::String("12".to_string()));
+        assert_eq!(values["gain"], CtxDataType::Float(2.0));
+        assert_eq!(values["enabled"], CtxDataType::Bool(false));
+        assert_eq!(values["unit"], CtxDataType::String("m3/h".to_string()));
+    }
+
+    #[test]
+    fn csv_reads_datetime_columns() {
+        let csv_content = r#"keys;commissioned;calibrated
+key1;31.05.2024;2024-06-01T14:30:00
+key2;01.12.2023;"#;
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{csv_content}").unwrap();
+
+        let layout = columns_layout(
+            r#"
+commissioned: { type: datetime, format: "%d.%m.%Y" }
+calibrated: { type: datetime }
+"#,
+        );
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .with_layout(layout);
+        let data = reader.read().unwrap();
+
+        let rendered = serde_json::to_value(&data["key1"]).unwrap();
+        assert_eq!(rendered["commissioned"], "2024-05-31");
+        assert_eq!(rendered["calibrated"], "2024-06-01T14:30:00");
+        assert_eq!(
+            serde_json::to_value(&data["key2"]).unwrap()["commissioned"],
+            "2023-12-01"
+        );
+    }
+
+    #[test]
+    fn csv_reports_all_column_violations() {
+        let csv_content = r#"keys;size;mode;tag
+key1;ten;auto;FI-101
+key2;2;manual;
+key3;3;off;fi101"#;
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{csv_content}").unwrap();
+
+        let layout = columns_layout(
+            r#"
+size: { type: int }
+mode: { enum: [auto, manual] }
+tag: { required: true, pattern: "[A-Z]+-\\d+" }
+"#,
+        );
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .with_layout(layout);
+        let message = reader.read().unwrap_err().to_string();
+        let file = file_name_of(tmp_file.path());
+
+        assert!(message.starts_with("4 invalid value(s)"));
+        for expected in [
+            format!("{file}, row 2, column 'size': 'ten' is not a valid int"),
+            format!("{file}, row 3, column 'tag': missing required value"),
+            format!("{file}, row 4, column 'mode': 'off' is not one of auto, manual"),
+            format!("{file}, row 4, column 'tag': 'fi101' does not match pattern '[A-Z]+-\\d+'"),
+        ] {
+            assert!(message.contains(&expected), "{message}");
+        }
+    }
+
+    #[test]
+    fn csv_errors_on_reserved_meta_column() {
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "keys;_meta\nkey1;note").unwrap();
+
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        );
+        let message = reader.read().unwrap_err().to_string();
+        assert!(
+            message.contains("Column '_meta' in ") && message.contains("is reserved"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn csv_errors_on_declared_column_not_in_file() {
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "keys;unit\nkey1;kg/h").unwrap();
+
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .with_layout(columns_layout("untis: { default: kg/h }"));
+        let message = reader.read().unwrap_err().to_string();
+        assert_eq!(
+            message,
+            format!(
+                "Column 'untis' not found in {}",
+                file_name_of(tmp_file.path())
+            )
+        );
+    }
+
+    #[test]
+    fn csv_reports_violations_with_key_error() {
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "keys;size\nkey1;ten\nkey1;2").unwrap();
+
+        let reader = CsvSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            std::path::Path::new(""),
+            Some(';'),
+        )
+        .with_layout(columns_layout("size: { type: int }"));
+        let message = reader.read().unwrap_err().to_string();
+        assert!(
+            message.starts_with("Duplicate key 'key1' in row 3"),
+            "{message}"
+        );
+        assert!(
+            message.contains("row 2, column 'size': 'ten' is not a valid int"),
+            "{message}"
+        );
+    }
 }
 
 #[cfg(test)]
@@ -652,4 +1856,431 @@ mod xlsxtests {
                 .contains("Worksheet 'nonexistent_sheet' not found")
         );
     }
+
+    #[test]
+    fn xlsx_read_reports_all_error_cells() {
+        let reader =
+            ExcelSourceReader::new("test.xlsx", Path::new("tests/testdata"), Some("Specials"))
+                .with_on_cell_error(OnCellError::Fail);
+        let message = reader.read().unwrap_err().to_string();
+        assert!(
+            message.starts_with("7 error cell(s) in 'test.xlsx'"),
+            "{message}"
+        );
+        assert!(
+            message.contains("sheet 'Specials', cell C2, column '"),
+            "{message}"
+        );
+        assert!(message.contains(": #DIV/0!"), "{message}");
+        assert!(message.contains(": #VALUE!"), "{message}");
+    }
+
+    #[test]
+    fn xlsx_read_error_cells_as_empty() {
+        let reader =
+            ExcelSourceReader::new("test.xlsx", Path::new("tests/testdata"), Some("Specials"))
+                .with_on_cell_error(OnCellError::Empty);
+        let data = reader.read().unwrap();
+        assert_eq!(data.len(), 7);
+        assert!(data.values().all(|row| {
+            row.values()
+                .all(|value| !matches!(value, CtxDataType::Error(_)))
+        }));
+    }
+
+    #[test]
+    fn column_name_works() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+
+    fn tags_layout() -> TableLayout {
+        TableLayout {
+            key: vec!["Area".to_string(), "Tag".to_string()],
+            header_row: Some(3),
+            data_start_row: Some(5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn xlsx_reads_table_from_named_range() {
+        let reader = ExcelSourceReader::new("wells.xlsx", Path::new("tests/testdata"), None)
+            .with_layout(TableLayout {
+                range: Some("TagTable".to_string()),
+                ..tags_layout()
+            });
+        let data = reader.read().unwrap();
+        assert_eq!(
+            data.keys().collect::<Vec<_>>(),
+            ["north_FI-101", "north_PI-101", "south_FI-101"]
+        );
+        assert_eq!(
+            data["north_PI-101"].get("Unit"),
+            Some(&CtxDataType::String("barg".to_string()))
+        );
+        // The three columns in the range and _meta
+        assert_eq!(data["north_PI-101"].len(), 4);
+        let Some(CtxDataType::Map(meta)) = data["north_PI-101"].get(META_COLUMN) else {
+            panic!("missing {META_COLUMN}");
+        };
+        assert_eq!(
+            meta.keys().collect::<Vec<_>>(),
+            ["file", "sheet", "row", "key"]
+        );
+        assert_eq!(
+            meta.get("file"),
+            Some(&CtxDataType::String("wells.xlsx".to_string()))
+        );
+        assert_eq!(
+            meta.get("sheet"),
+            Some(&CtxDataType::String("Tags".to_string()))
+        );
+        assert_eq!(meta.get("row"), Some(&CtxDataType::Int(6)));
+    }
+
+    #[test]
+    fn xlsx_reads_table_from_cell_range_with_separator() {
+        let reader =
+            ExcelSourceReader::new("wells.xlsx", Path::new("tests/testdata"), Some("Tags"))
+                .with_layout(TableLayout {
+                    key_separator: Some("/".to_string()),
+                    range: Some("C3:E7".to_string()),
+                    ..tags_layout()
+                });
+        let data = reader.read().unwrap();
+        assert!(data.contains_key("south/FI-101"));
+    }
+
+    #[test]
+    fn xlsx_read_errors_on_duplicate_key() {
+        let reader =
+            ExcelSourceReader::new("wells.xlsx", Path::new("tests/testdata"), Some("Tags"))
+                .with_layout(TableLayout {
+                    key: vec!["Tag".to_string()],
+                    range: Some("$C$3:$E$7".to_string()),
+                    ..tags_layout()
+                });
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Duplicate key 'FI-101' in row 7")
+        );
+    }
+
+    #[test]
+    fn xlsx_read_errors_on_missing_key_value() {
+        let reader =
+            ExcelSourceReader::new("wells.xlsx", Path::new("tests/testdata"), Some("Tags"))
+                .with_layout(tags_layout());
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Row 9 has no value in key column 'Tag'")
+        );
+    }
+
+    #[test]
+    fn xlsx_read_errors_on_unknown_key_column() {
+        let reader =
+            ExcelSourceReader::new("wells.xlsx", Path::new("tests/testdata"), Some("Tags"))
+                .with_layout(TableLayout {
+                    key: vec!["Description".to_string()],
+                    ..tags_layout()
+                });
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Key column 'Description' not found")
+        );
+    }
+
+    fn read_wells_north(file_name: &str) -> DataSourceRows {
+        ExcelSourceReader::new(file_name, Path::new("tests/testdata"), Some("Wells_North"))
+            .read()
+            .unwrap()
+    }
+
+    fn assert_wells_north(data: &DataSourceRows) {
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["N1", "N2"]);
+        assert_eq!(data["N1"].get("depth"), Some(&CtxDataType::Int(100)));
+        assert_eq!(data["N2"].get("depth"), Some(&CtxDataType::Float(200.5)));
+    }
+
+    #[test]
+    fn xlsm_read_works() {
+        assert_wells_north(&read_wells_north("wells.xlsm"));
+    }
+
+    #[test]
+    fn xls_read_works() {
+        assert_wells_north(&read_wells_north("wells.xls"));
+    }
+
+    #[test]
+    fn ods_read_works() {
+        assert_wells_north(&read_wells_north("wells.ods"));
+    }
+
+    #[test]
+    fn ods_reads_time_and_date_cells() {
+        let data = ExcelSourceReader::new("times.ods", Path::new("tests/testdata"), Some("Shifts"))
+            .read()
+            .unwrap();
+        assert_eq!(data["Day"]["start"], CtxDataType::DateTime(0.3125));
+        assert_eq!(data["Day"]["length"], CtxDataType::DateTime(0.5));
+        assert_eq!(data["Long"]["length"], CtxDataType::DateTime(1.25));
+        assert_eq!(
+            data["Day"]["date"],
+            CtxDataType::DateTime(45443.604166666664)
+        );
+        assert_eq!(data["Long"]["date"], CtxDataType::DateTime(45443.0));
+    }
+
+    #[test]
+    fn spreadsheet_skips_empty_rows() {
+        let data = ExcelSourceReader::new("gaps.ods", Path::new("tests/testdata"), Some("Wells"))
+            .read()
+            .unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["N1", "N2"]);
+        let Some(CtxDataType::Map(meta)) = data["N2"].get(META_COLUMN) else {
+            panic!("missing {META_COLUMN}");
+        };
+        assert_eq!(meta.get("row"), Some(&CtxDataType::Int(4)));
+    }
+
+    #[test]
+    fn parse_duration_iso_works() {
+        assert_eq!(parse_duration_iso("PT18H00M00S"), Some(0.75));
+        assert_eq!(parse_duration_iso("P2D"), Some(2.0));
+        assert_eq!(parse_duration_iso("-PT12H"), Some(-0.5));
+        assert_eq!(parse_duration_iso("PT1.5S"), Some(1.5 / 86_400.0));
+        assert_eq!(parse_duration_iso("12:00"), None);
+        assert_eq!(parse_duration_iso("PT5X"), None);
+    }
+
+    #[test]
+    fn matching_sheets_works_for_all_formats() {
+        for file_name in ["wells.xlsx", "wells.xlsm", "wells.xls", "wells.ods"] {
+            let sheets = ExcelSourceReader::matching_sheets(
+                file_name,
+                Path::new("tests/testdata"),
+                &["Wells_*"],
+            )
+            .unwrap();
+            assert_eq!(sheets, ["Wells_North", "Wells_South"], "{file_name}");
+        }
+    }
+
+    #[test]
+    fn parse_cell_range_works() {
+        assert_eq!(parse_cell_range("A1:B2"), Some(((0, 0), (1, 1))));
+        assert_eq!(parse_cell_range("$AA$10:$ab$20"), Some(((9, 26), (19, 27))));
+        assert_eq!(parse_cell_range("TagTable"), None);
+        assert_eq!(parse_cell_range("A0:B2"), None);
+    }
+}
+
+#[cfg(test)]
+mod structuredtests {
+    use super::*;
+    use std::io::Write;
+
+    fn key_layout(key: &str) -> TableLayout {
+        TableLayout {
+            key: vec![key.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn write_tmp(content: &str) -> tempfile::NamedTempFile {
+        let mut tmp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(tmp_file, "{content}").unwrap();
+        tmp_file
+    }
+
+    #[test]
+    fn json_reads_list_keyed_by_field() {
+        let tmp_file = write_tmp(
+            r#"[
+  {"tag": "FI-101", "range": [0, 100.5], "alarm": {"high": 90, "enabled": true}},
+  {"tag": "FI-102", "range": [0, 50], "alarm": null}
+]"#,
+        );
+        let reader = JsonSourceReader::new(tmp_file.path().to_str().unwrap(), Path::new(""))
+            .with_layout(key_layout("tag"));
+        let data = reader.read().unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["FI-101", "FI-102"]);
+
+        let row = &data["FI-101"];
+        assert_eq!(
+            row.get("range"),
+            Some(&CtxDataType::List(vec![
+                CtxDataType::Int(0),
+                CtxDataType::Float(100.5)
+            ]))
+        );
+        let Some(CtxDataType::Map(alarm)) = row.get("alarm") else {
+            panic!("Expected a map");
+        };
+        assert_eq!(alarm.get("high"), Some(&CtxDataType::Int(90)));
+        assert_eq!(alarm.get("enabled"), Some(&CtxDataType::Bool(true)));
+        assert_eq!(data["FI-102"].get("alarm"), Some(&CtxDataType::Empty));
+    }
+
+    #[test]
+    fn json_nested_values_render_as_maps_and_lists() {
+        let tmp_file = write_tmp(r#"{"one": {"limits": {"lo": 1, "hi": 2}, "tags": ["a", "b"]}}"#);
+        let reader = JsonSourceReader::new(tmp_file.path().to_str().unwrap(), Path::new(""));
+        let data = reader.read().unwrap();
+        let env = minijinja::Environment::new();
+        let result = env
+            .render_str(
+                "{{ limits.hi }} {% for t in tags %}{{ t }}{% endfor %} {{ tags|length }}",
+                &data["one"],
+            )
+            .unwrap();
+        assert_eq!(result, "2 ab 2");
+    }
+
+    #[test]
+    fn json_errors_on_list_without_key() {
+        let tmp_file = write_tmp(r#"[{"tag": "one"}]"#);
+        let reader = JsonSourceReader::new(tmp_file.path().to_str().unwrap(), Path::new(""));
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("specify the field")
+        );
+    }
+
+    #[test]
+    fn json_errors_on_duplicate_key() {
+        let tmp_file = write_tmp(r#"[{"tag": "one"}, {"tag": "one"}]"#);
+        let reader = JsonSourceReader::new(tmp_file.path().to_str().unwrap(), Path::new(""))
+            .with_layout(key_layout("tag"));
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Duplicate key 'one' in row 2")
+        );
+    }
+
+    #[test]
+    fn yaml_reads_map_of_rows() {
+        let tmp_file = write_tmp(
+            r#"
+two:
+  value: 2
+  units: [bar, barg]
+one:
+  value: 1.5
+"#,
+        );
+        let reader = YamlSourceReader::new(tmp_file.path().to_str().unwrap(), Path::new(""))
+            .with_layout(key_layout("tag"));
+        let data = reader.read().unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["two", "one"]);
+        assert_eq!(
+            data["two"].get("tag"),
+            Some(&CtxDataType::String("two".to_string()))
+        );
+        assert_eq!(data["one"].get("value"), Some(&CtxDataType::Float(1.5)));
+        assert_eq!(
+            data["two"].get("units"),
+            Some(&CtxDataType::List(vec![
+                CtxDataType::String("bar".to_string()),
+                CtxDataType::String("barg".to_string())
+            ]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod sqlitetests {
+    use super::*;
+
+    fn create_db() -> tempfile::NamedTempFile {
+        let tmp_file = tempfile::NamedTempFile::new().unwrap();
+        let conn = rusqlite::Connection::open(tmp_file.path()).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tags (tag TEXT, area TEXT, value REAL, count INTEGER, comment TEXT);
+             INSERT INTO tags VALUES ('FI-101', 'north', 1.5, 1, NULL);
+             INSERT INTO tags VALUES ('FI-102', 'south', 2.25, 2, 'spare');
+             INSERT INTO tags VALUES ('FI-103', 'north', 3.0, 3, '');",
+        )
+        .unwrap();
+        tmp_file
+    }
+
+    #[test]
+    fn sqlite_reads_query_result() {
+        let tmp_file = create_db();
+        let reader = SqliteSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            Path::new(""),
+            "SELECT tag, value, count, comment FROM tags WHERE area = 'north' ORDER BY tag DESC",
+        );
+        let data = reader.read().unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["FI-103", "FI-101"]);
+
+        let row = &data["FI-101"];
+        assert_eq!(
+            row.get("tag"),
+            Some(&CtxDataType::String("FI-101".to_string()))
+        );
+        assert_eq!(row.get("value"), Some(&CtxDataType::Float(1.5)));
+        assert_eq!(row.get("count"), Some(&CtxDataType::Int(1)));
+        assert_eq!(row.get("comment"), Some(&CtxDataType::Empty));
+        assert!(row.get("area").is_none());
+    }
+
+    #[test]
+    fn sqlite_errors_on_first_column_not_string() {
+        let tmp_file = create_db();
+        let reader = SqliteSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            Path::new(""),
+            "SELECT count, tag FROM tags",
+        );
+        let result = reader.read();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("First column must contain strings only")
+        );
+    }
+
+    #[test]
+    fn sqlite_errors_on_invalid_query() {
+        let tmp_file = create_db();
+        let reader = SqliteSourceReader::new(
+            tmp_file.path().to_str().unwrap(),
+            Path::new(""),
+            "SELECT tag FROM missing",
+        );
+        let result = reader.read();
+        assert!(result.unwrap_err().to_string().contains("Invalid query"));
+    }
+
+    #[test]
+    fn sqlite_errors_on_missing_file() {
+        let reader = SqliteSourceReader::new("missing.db", Path::new(""), "SELECT 1");
+        assert!(reader.read().is_err());
+        assert!(!Path::new("missing.db").exists());
+    }
 }
@@ -1,4 +1,7 @@
+pub mod cache;
 pub mod commands;
 pub mod config;
 pub mod datasource;
 pub mod renderer;
+pub mod septic;
+pub mod sourcemap;
@@ -18,5 +18,7 @@ fn main() {
         Commands::Update(update) => update.execute(),
         Commands::Drawio(drawio) => drawio.execute(),
         Commands::Schema(schema) => schema.execute(),
+        Commands::Lint(lint) => lint.execute(),
+        Commands::Blame(blame) => blame.execute(),
     }
 }
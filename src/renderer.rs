@@ -1,14 +1,18 @@
 use crate::config::Counter as CounterConfig;
 use crate::config::{self, RowFiltering};
-use crate::datasource::DataSourceRows;
+use crate::datasource::{
+    CtxDataType, DataSourceRows, META_COLUMN, display_value, excel_serial_to_datetime,
+    parse_datetime,
+};
+use crate::septic::KEY_COLUMN;
 use anyhow::Context;
 use chrono::Local;
+use indexmap::IndexMap;
 use minijinja::value::{Kwargs, Rest, Value, ValueKind, from_args};
-use minijinja::{Environment, Error, ErrorKind};
+use minijinja::{Environment, Error, ErrorKind, State, UndefinedBehavior};
 use serde::Serialize;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -50,8 +54,20 @@ impl CounterMap {
     }
 }
 
-fn filt_unpack(v: Value, unpack_keys: Rest<Value>) -> Result<Value, Error> {
+fn filt_unpack(state: &State, v: Value, unpack_keys: Rest<Value>) -> Result<Value, Error> {
     let (item_keys, _): (&[Value], Kwargs) = from_args(&unpack_keys)?;
+    // Missing keys give undefined values, unless undefined values are errors
+    let strict = state.undefined_behavior() == UndefinedBehavior::Strict;
+    let get_item = |value: &Value, key: &Value| -> Result<Value, Error> {
+        let item = value.get_item(key)?;
+        if strict && item.is_undefined() {
+            return Err(Error::new(
+                ErrorKind::UndefinedError,
+                format!("key '{key}' not found"),
+            ));
+        }
+        Ok(item)
+    };
     match v.kind() {
         ValueKind::Map => {
             let items_are_maps = v
@@ -60,11 +76,12 @@ fn filt_unpack(v: Value, unpack_keys: Rest<Value>) -> Result<Value, Error> {
                 .all(|key| v.get_item(&key).unwrap().kind() == ValueKind::Map);
             if !items_are_maps {
                 let rv = match item_keys.len() {
-                    1 => v.get_item(&item_keys[0]).unwrap(),
+                    1 => get_item(&v, &item_keys[0])?,
                     _ => item_keys
                         .iter()
-                        .map(|key| v.get_item(key).unwrap())
-                        .collect(),
+                        .map(|key| get_item(&v, key))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .into(),
                 };
                 Ok(rv)
             } else {
@@ -86,16 +103,16 @@ fn filt_unpack(v: Value, unpack_keys: Rest<Value>) -> Result<Value, Error> {
                     .map(|value| {
                         let inner_vec: Vec<Value> = item_keys
                             .iter()
-                            .map(|key| value.get_item(key).unwrap())
-                            .collect();
+                            .map(|key| get_item(&value, key))
+                            .collect::<Result<_, _>>()?;
                         if item_keys.len() == 1 {
-                            inner_vec.into_iter().next().unwrap()
+                            Ok(inner_vec.into_iter().next().unwrap())
                         } else {
-                            Value::from(inner_vec)
+                            Ok(Value::from(inner_vec))
                         }
                     })
-                    .collect();
-                Ok(rv)
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Ok(rv.into())
             } else {
                 Err(Error::new(
                     ErrorKind::CannotUnpack,
@@ -151,11 +168,207 @@ fn filt_bitmask(value: Value, length: Option<usize>) -> Result<String, Error> {
     Ok(mask.into_iter().collect())
 }
 
+/// Format a date with a strftime format. The value is either an ISO-8601 date or date and time,
+/// which is how dates from Excel and datetime columns are rendered, or an Excel serial number.
+fn filt_dateformat(value: Value, format: &str) -> Result<String, Error> {
+    let datetime = match value.kind() {
+        ValueKind::String => value.as_str().and_then(|s| parse_datetime(s.trim(), None)),
+        ValueKind::Number => f64::try_from(value.clone())
+            .ok()
+            .and_then(excel_serial_to_datetime),
+        _ => None,
+    };
+    let datetime = datetime.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("cannot read '{value}' as a date"),
+        )
+    })?;
+    let mut formatted = String::new();
+    std::fmt::Write::write_fmt(&mut formatted, format_args!("{}", datetime.format(format)))
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("invalid date format '{format}'"),
+            )
+        })?;
+    Ok(formatted)
+}
+
+fn invalid_value(value: &Value, kind: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidOperation,
+        format!("cannot read '{value}' as {kind}"),
+    )
+}
+
+/// Quote a value as a Septic string. Septic strings have no escape sequences, so double quotes
+/// are replaced with single quotes and line breaks with spaces.
+fn filt_septic_str(value: Value) -> Result<String, Error> {
+    let text = match value.kind() {
+        ValueKind::Undefined => return Err(Error::from(ErrorKind::UndefinedError)),
+        ValueKind::None => String::new(),
+        ValueKind::String => value.as_str().unwrap_or_default().to_string(),
+        ValueKind::Bool | ValueKind::Number => value.to_string(),
+        _ => return Err(invalid_value(&value, "a string")),
+    };
+    let text = text.replace("\r\n", " ").replace(['\r', '\n'], " ");
+    Ok(format!("\"{}\"", text.replace('"', "'")))
+}
+
+/// Render a value as the Septic switch ON or OFF. Accepts booleans, numbers and the texts
+/// true/false, on/off, yes/no and 1/0 in any case.
+fn filt_septic_bool(value: Value) -> Result<String, Error> {
+    let on = match value.kind() {
+        ValueKind::Undefined => return Err(Error::from(ErrorKind::UndefinedError)),
+        ValueKind::Bool | ValueKind::Number => value.is_true(),
+        ValueKind::String => {
+            match value
+                .as_str()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "true" | "on" | "yes" | "1" => true,
+                "false" | "off" | "no" | "0" => false,
+                _ => return Err(invalid_value(&value, "a boolean")),
+            }
+        }
+        _ => return Err(invalid_value(&value, "a boolean")),
+    };
+    Ok(if on { "ON" } else { "OFF" }.to_string())
+}
+
+/// Write a number the way Septic reads it: never with an exponent, and with either a fixed
+/// number of decimals or a number of significant digits
+fn septic_num(
+    value: &Value,
+    decimals: Option<usize>,
+    digits: Option<usize>,
+) -> Result<String, Error> {
+    let number = match value.kind() {
+        ValueKind::Undefined => return Err(Error::from(ErrorKind::UndefinedError)),
+        ValueKind::Number => f64::try_from(value.clone()).ok(),
+        ValueKind::String => value.as_str().and_then(|s| s.trim().parse::<f64>().ok()),
+        _ => None,
+    }
+    .filter(|number| number.is_finite())
+    .ok_or_else(|| invalid_value(value, "a number"))?;
+
+    let formatted = match (decimals, digits) {
+        (Some(_), Some(_)) => {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "use either decimals or digits, not both",
+            ));
+        }
+        (Some(decimals), None) => format!("{number:.decimals$}"),
+        (None, Some(0)) => {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "digits must be at least 1",
+            ));
+        }
+        (None, Some(digits)) if number == 0.0 => format!("{:.*}", digits - 1, 0.0),
+        (None, Some(digits)) => {
+            let decimals = |number: f64| digits as i32 - 1 - number.abs().log10().floor() as i32;
+            let round = |number: f64, decimals: i32| {
+                if decimals >= 0 {
+                    format!("{number:.*}", decimals as usize)
+                } else {
+                    let scale = 10f64.powi(-decimals);
+                    format!("{:.0}", (number / scale).round() * scale)
+                }
+            };
+            let rounded = round(number, decimals(number));
+            // Rounding up to the next power of ten, like 9.99 to 10.0, adds a digit
+            match rounded.parse::<f64>() {
+                Ok(carried) if carried != 0.0 && decimals(carried) < decimals(number) => {
+                    round(carried, decimals(carried))
+                }
+                _ => rounded,
+            }
+        }
+        (None, None) => match value.as_i64() {
+            Some(int) if value.is_integer() => int.to_string(),
+            _ => number.to_string(),
+        },
+    };
+    // Rounding a small negative number gives "-0" or "-0.00", which Septic should not see
+    Ok(match formatted.strip_prefix('-') {
+        Some(unsigned) if unsigned.chars().all(|c| c == '0' || c == '.') => unsigned.to_string(),
+        _ => formatted,
+    })
+}
+
+fn filt_septic_num(value: Value, decimals: Option<usize>, kwargs: Kwargs) -> Result<String, Error> {
+    let digits: Option<usize> = kwargs.get("digits")?;
+    kwargs.assert_all_used()?;
+    septic_num(&value, decimals, digits)
+}
+
+/// Write a sequence as a Septic list: the number of items followed by the items, with strings
+/// quoted and numbers as they are
+fn filt_septic_list(value: Value) -> Result<String, Error> {
+    match value.kind() {
+        ValueKind::Undefined => return Err(Error::from(ErrorKind::UndefinedError)),
+        ValueKind::Seq | ValueKind::Iterable => {}
+        _ => return Err(invalid_value(&value, "a list")),
+    }
+    let items = value
+        .try_iter()?
+        .map(|item| match item.kind() {
+            ValueKind::Number => septic_num(&item, None, None),
+            ValueKind::Bool => filt_septic_bool(item),
+            _ => filt_septic_str(item),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(std::iter::once(items.len().to_string())
+        .chain(items)
+        .collect::<Vec<_>>()
+        .join("  "))
+}
+
+/// Right-align `Key=` to the column Septic attributes are aligned to
+fn filt_septic_key(key: &str) -> String {
+    format!("{:>width$}", format!("{key}="), width = KEY_COLUMN)
+}
+
 fn func_timestamp(format: Option<&str>) -> String {
     let fmt = format.unwrap_or("%Y-%m-%d %H:%M:%S");
     Local::now().format(fmt).to_string()
 }
 
+/// The value in `column` of the row with `key` in a source, or the whole row without `_meta` if
+/// no column is given. Undefined if the row or column does not exist.
+fn func_lookup(
+    source_data: &HashMap<String, DataSourceRows>,
+    source_id: &str,
+    key: &str,
+    column: Option<&str>,
+) -> Result<Value, Error> {
+    let rows = source_data.get(source_id).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("unknown source '{source_id}'"),
+        )
+    })?;
+    let Some(row) = rows.get(key) else {
+        return Ok(Value::UNDEFINED);
+    };
+    Ok(match column {
+        Some(column) => row
+            .get(column)
+            .map_or(Value::UNDEFINED, Value::from_serialize),
+        None => Value::from_serialize(
+            row.iter()
+                .filter(|(column, _)| *column != META_COLUMN)
+                .collect::<BTreeMap<_, _>>(),
+        ),
+    })
+}
+
 fn global_gitcommit(long: bool) -> String {
     let args = if long {
         ["rev-parse", "--verify", "HEAD"]
@@ -184,49 +397,159 @@ fn erroring_formatter(
     minijinja::escape_formatter(out, state, value)
 }
 
-#[allow(clippy::unnecessary_wraps)]
-fn load_template(
-    template_path: &Path,
-    encoding_name: &str,
-    name: &str,
-) -> Result<Option<String>, Error> {
-    let mut path = PathBuf::from(template_path);
-    path.push(name);
-
-    let file = match File::open(path) {
-        Ok(f) => f,
-        Err(err) => match err.kind() {
-            std::io::ErrorKind::NotFound => return Ok(None),
-            other_error => {
-                dbg!(&other_error);
-                panic!("Unknown error, please report it");
-            }
-        },
+/// The message for a template error. For undefined values, it includes the expression that was
+/// undefined if the template source is available.
+pub fn error_message(err: &Error) -> String {
+    let expression = err
+        .template_source()
+        .zip(err.range())
+        .and_then(|(source, range)| failing_expression(source, range));
+    match (err.kind(), err.detail(), expression, err.name()) {
+        (ErrorKind::UndefinedError, None, Some(expression), Some(name)) => format!(
+            "{}: '{expression}' (in {name}:{})",
+            err.kind(),
+            err.line().unwrap_or(0)
+        ),
+        _ => err.to_string(),
+    }
+}
+
+/// The source of the failing expression. The range of a failing filter only covers the filter, so
+/// the value before it is included, e.g. `row.unit | upper`.
+fn failing_expression(source: &str, range: std::ops::Range<usize>) -> Option<&str> {
+    let before = source.get(..range.start)?.trim_end();
+    let Some(operand) = before.strip_suffix('|').map(str::trim_end) else {
+        return source.get(range);
+    };
+    let start = operand
+        .rfind(|c: char| !(c.is_alphanumeric() || "_.[]'\"".contains(c)))
+        .map_or(0, |i| i + 1);
+    source.get(start..range.end)
+}
+
+/// The content of a template file decoded with the encoding, or None if the file does not exist.
+/// Errors name the file, so that they can be told apart from errors in the template itself.
+fn load_template(path: &Path, encoding_name: &str) -> Result<Option<String>, Error> {
+    let read_error = |err: std::io::Error| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("cannot read template file '{}': {err}", path.display()),
+        )
+        .with_source(err)
     };
-    let encoding = encoding_rs::Encoding::for_label(encoding_name.as_bytes())
-        .expect("cmd_make: Unknown encoding");
-    let mut reader = encoding_rs_io::DecodeReaderBytesBuilder::new()
-        .encoding(Some(encoding))
-        .build(file);
-    let mut content = String::new();
-
-    match reader.read_to_string(&mut content) {
-        Ok(_) => Ok(Some(content)),
-        Err(err) => {
-            dbg!(&err);
-            panic!("Unknown error when reading template, please report it");
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(read_error(err)),
+    };
+    let encoding = encoding_rs::Encoding::for_label(encoding_name.as_bytes()).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "cannot read template file '{}': unknown encoding '{encoding_name}'",
+                path.display()
+            ),
+        )
+    })?;
+    let (content, encoding, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        let line = content
+            .lines()
+            .position(|line| line.contains(char::REPLACEMENT_CHARACTER))
+            .map_or(0, |index| index + 1);
+        return Err(read_error(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("not valid {} (line {line})", encoding.name()),
+        )));
+    }
+    Ok(Some(content.into_owned()))
+}
+
+/// Rows grouped by their value in `column`, with groups in the order they first appear
+fn group_rows(
+    rows: DataSourceRows,
+    column: &str,
+) -> Vec<(CtxDataType, Vec<HashMap<String, CtxDataType>>)> {
+    let mut groups: IndexMap<String, (CtxDataType, Vec<_>)> = IndexMap::new();
+    for row in rows.into_values() {
+        let value = row.get(column).cloned().unwrap_or(CtxDataType::Empty);
+        groups
+            .entry(display_value(&value))
+            .or_insert_with(|| (value, Vec::new()))
+            .1
+            .push(row);
+    }
+    groups.into_values().collect()
+}
+
+/// The template and source row that produced a part of the rendered output
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    pub template: String,
+    pub source: Option<String>,
+    pub row: Option<String>,
+    /// Byte offset in the rendered output where this part starts
+    pub offset: usize,
+}
+
+/// The source row or group that was rendered when a template failed, added as context to the error
+#[derive(Debug)]
+pub struct RowContext(String);
+
+impl RowContext {
+    fn row(source: &str, key: &str, row: &HashMap<String, CtxDataType>) -> Self {
+        let mut description = format!("row '{key}' in source '{source}'");
+        if let Some(CtxDataType::Map(meta)) = row.get(META_COLUMN) {
+            let mut location = Vec::new();
+            if let Some(file) = meta.get("file") {
+                location.push(display_value(file));
+            }
+            if let Some(sheet) = meta.get("sheet") {
+                location.push(format!("sheet '{}'", display_value(sheet)));
+            }
+            if let Some(row_number) = meta.get("row") {
+                location.push(format!("row {}", display_value(row_number)));
+            }
+            description = format!("{description} ({})", location.join(", "));
         }
+        Self(description)
+    }
+
+    fn group(source: &str, group: &CtxDataType) -> Self {
+        Self(format!(
+            "group '{}' in source '{source}'",
+            display_value(group)
+        ))
     }
 }
 
+impl std::fmt::Display for RowContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "while rendering {}", self.0)
+    }
+}
+
+/// A file with macros that is imported into every template under `namespace`
+#[derive(Clone, Debug)]
+struct MacroFile {
+    name: String,
+    namespace: String,
+    path: PathBuf,
+}
+
+#[derive(Clone)]
 pub struct MiniJinja<'a> {
     pub env: Environment<'a>,
+    globals: Vec<String>,
+    macros: Vec<MacroFile>,
 }
 
 impl<'a> MiniJinja<'a> {
     pub fn new(globals: &[String]) -> anyhow::Result<MiniJinja<'a>> {
         let mut renderer = MiniJinja {
             env: Environment::new(),
+            globals: globals.to_vec(),
+            macros: Vec::new(),
         };
         renderer.add_globals(globals);
         renderer
@@ -240,6 +563,12 @@ impl<'a> MiniJinja<'a> {
             .add_global("gitcommitlong", global_gitcommit(true));
         renderer.env.add_function("now", func_timestamp);
         renderer.env.add_filter("bitmask", filt_bitmask);
+        renderer.env.add_filter("dateformat", filt_dateformat);
+        renderer.env.add_filter("septic_str", filt_septic_str);
+        renderer.env.add_filter("septic_bool", filt_septic_bool);
+        renderer.env.add_filter("septic_list", filt_septic_list);
+        renderer.env.add_filter("septic_num", filt_septic_num);
+        renderer.env.add_filter("septic_key", filt_septic_key);
         renderer.env.add_filter("values", filt_values);
         renderer.env.add_filter("unpack", filt_unpack);
         renderer.env.set_formatter(erroring_formatter);
@@ -271,10 +600,94 @@ impl<'a> MiniJinja<'a> {
         Ok(())
     }
 
-    pub fn set_loader(&mut self, template_path: &Path, encoding: &str) -> anyhow::Result<()> {
-        let template_path = template_path.to_path_buf();
+    /// Make each source available as a global list of rows, and rows and values in any source
+    /// available through `lookup(source_id, key, column)`
+    pub fn set_sources(&mut self, source_data: &HashMap<String, DataSourceRows>) {
+        for (key, rows) in source_data {
+            self.env.add_global(
+                key.clone(),
+                Value::from_serialize(rows.values().collect::<Vec<_>>()),
+            );
+        }
+        let source_data = Arc::new(source_data.clone());
+        self.env.add_function(
+            "lookup",
+            move |source_id: &str, key: &str, column: Option<&str>| {
+                func_lookup(&source_data, source_id, key, column)
+            },
+        );
+    }
+
+    /// Make any use of an undefined value an error, not only printing it. Debug info is kept so
+    /// that errors can tell which expression was undefined.
+    pub fn set_strict(&mut self, strict: bool) {
+        if strict {
+            self.env.set_undefined_behavior(UndefinedBehavior::Strict);
+            self.env.set_debug(true);
+        }
+    }
+
+    /// Import the macros in each file into every template loaded afterwards by `set_loader`. The
+    /// namespace is the file name without extension.
+    pub fn set_macros(&mut self, relative_root: &Path, filenames: &[&str]) -> anyhow::Result<()> {
+        for filename in filenames {
+            let path = relative_root.join(filename);
+            if !path.is_file() {
+                anyhow::bail!("Macro file '{}' not found", path.display());
+            }
+            let namespace = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let is_identifier = namespace
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && namespace
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_identifier {
+                anyhow::bail!(
+                    "Macro file '{filename}' cannot be imported as '{namespace}'. Rename it so the name without extension only has letters, digits and underscores"
+                );
+            }
+            if let Some(other) = self.macros.iter().find(|m| m.namespace == namespace) {
+                anyhow::bail!(
+                    "Macro files '{}' and '{filename}' would both be imported as '{namespace}'",
+                    other.name
+                );
+            }
+            self.macros.push(MacroFile {
+                name: (*filename).to_string(),
+                namespace,
+                path,
+            });
+        }
+        Ok(())
+    }
+
+    /// Load templates from the first of `template_paths` that has a template with the name, so
+    /// that earlier directories override later ones
+    pub fn set_loader(&mut self, template_paths: &[PathBuf], encoding: &str) -> anyhow::Result<()> {
+        let template_paths = template_paths.to_vec();
         let encoding = encoding.to_string();
-        let loader = move |name: &str| load_template(&template_path, &encoding, name);
+        let macros = self.macros.clone();
+        // On the first line, so that line numbers in errors are unchanged
+        let imports: String = macros
+            .iter()
+            .map(|m| format!("{{% import {:?} as {} %}}", m.name, m.namespace))
+            .collect();
+        let loader = move |name: &str| {
+            if let Some(macro_file) = macros.iter().find(|m| m.name == name) {
+                return load_template(&macro_file.path, &encoding);
+            }
+            for template_path in &template_paths {
+                if let Some(content) = load_template(&template_path.join(name), &encoding)? {
+                    return Ok(Some(format!("{imports}{content}")));
+                }
+            }
+            Ok(None)
+        };
         self.env.set_loader(loader);
         Ok(())
     }
@@ -291,7 +704,21 @@ impl<'a> MiniJinja<'a> {
         source_data: &HashMap<String, DataSourceRows>,
         adjust_spacing: bool,
     ) -> anyhow::Result<String> {
+        let (rendered, _origins) =
+            self.render_template_with_origins(template, source_data, adjust_spacing)?;
+        Ok(rendered)
+    }
+
+    /// Render a layout item and record which template and source row produced each part of
+    /// the output. Origin offsets are relative to the start of the returned string.
+    pub fn render_template_with_origins(
+        &self,
+        template: &config::Template,
+        source_data: &HashMap<String, DataSourceRows>,
+        adjust_spacing: bool,
+    ) -> anyhow::Result<(String, Vec<Origin>)> {
         let mut rendered = String::new();
+        let mut origins = Vec::new();
 
         if let Some(src_name) = &template.source {
             let source_rows = source_data.get(src_name).with_context(|| {
@@ -305,16 +732,62 @@ impl<'a> MiniJinja<'a> {
                 .apply_filters(source_rows, &self.env)
                 .with_context(|| format!("template {:?}", &template.name))?;
 
-            for (_key, row) in filtered_data {
-                let mut tmpl_rend = self.render(&template.name, Some(row))?;
+            if let Some(column) = &template.group_by {
+                if !source_rows.is_empty()
+                    && !source_rows.values().any(|row| row.contains_key(column))
+                {
+                    anyhow::bail!(
+                        "unknown column '{column}' in group_by of {}, not found in source '{src_name}'",
+                        template.name
+                    );
+                }
+                for (group, rows) in group_rows(filtered_data, column) {
+                    origins.push(Origin {
+                        template: template.name.clone(),
+                        source: Some(src_name.clone()),
+                        row: Some(display_value(&group)),
+                        offset: rendered.len(),
+                    });
+                    let ctx = minijinja::context! {
+                        group => Value::from_serialize(&group),
+                        rows => Value::from_serialize(&rows),
+                    };
+                    let mut tmpl_rend = self
+                        .render(&template.name, ctx)
+                        .with_context(|| RowContext::group(src_name, &group))?;
+
+                    if adjust_spacing {
+                        tmpl_rend = tmpl_rend.trim_end().to_string();
+                        tmpl_rend.push_str("\r\n\r\n");
+                    }
+                    rendered.push_str(&tmpl_rend);
+                }
+            } else {
+                for (key, row) in filtered_data {
+                    origins.push(Origin {
+                        template: template.name.clone(),
+                        source: Some(src_name.clone()),
+                        row: Some(key.clone()),
+                        offset: rendered.len(),
+                    });
+                    let mut tmpl_rend = self
+                        .render(&template.name, Some(&row))
+                        .with_context(|| RowContext::row(src_name, &key, &row))?;
 
-                if adjust_spacing {
-                    tmpl_rend = tmpl_rend.trim_end().to_string();
-                    tmpl_rend.push_str("\r\n\r\n");
+                    if adjust_spacing {
+                        tmpl_rend = tmpl_rend.trim_end().to_string();
+                        tmpl_rend.push_str("\r\n\r\n");
+                    }
+                    rendered.push_str(&tmpl_rend);
                 }
-                rendered.push_str(&tmpl_rend);
             }
         } else {
+            origins.push(Origin {
+                template: template.name.clone(),
+                source: None,
+                row: None,
+                offset: 0,
+            });
             rendered = self.render(&template.name, minijinja::context!())?;
         }
 
@@ -323,7 +796,21 @@ impl<'a> MiniJinja<'a> {
             rendered.push_str("\r\n\r\n");
         }
 
-        Ok(rendered)
+        Ok((rendered, origins))
+    }
+
+    /// Create a copy of the renderer with additional global variables from the config file.
+    /// Global variables given on the command line take precedence over these.
+    pub fn with_config_globals(&self, globals: &BTreeMap<String, serde_json::Value>) -> Self {
+        let mut renderer = self.clone();
+        for (key, value) in globals {
+            renderer
+                .env
+                .add_global(key.clone(), Value::from_serialize(value));
+        }
+        let cli_globals = renderer.globals.clone();
+        renderer.add_globals(&cli_globals);
+        renderer
     }
 
     fn add_globals(&mut self, globals: &[String]) {
@@ -568,4 +1055,382 @@ mod tests {
                 .contains("value is larger than")
         );
     }
+
+    #[test]
+    fn filt_dateformat_formats_dates() {
+        let mut env = Environment::new();
+        env.add_filter("dateformat", filt_dateformat);
+        let render = |template: &str, value: Value| env.render_str(template, context!(d => value));
+        assert_eq!(
+            render(
+                "{{ d | dateformat('%d.%m.%Y') }}",
+                Value::from("2024-05-31")
+            )
+            .unwrap(),
+            "31.05.2024"
+        );
+        assert_eq!(
+            render(
+                "{{ d | dateformat('%H:%M') }}",
+                Value::from("2024-05-31T14:30:00")
+            )
+            .unwrap(),
+            "14:30"
+        );
+        assert_eq!(
+            render("{{ d | dateformat('%Y-%m-%d') }}", Value::from(45443.0)).unwrap(),
+            "2024-05-31"
+        );
+        assert!(
+            render("{{ d | dateformat('%Y') }}", Value::from("soon"))
+                .unwrap_err()
+                .to_string()
+                .contains("cannot read 'soon' as a date")
+        );
+    }
+
+    #[test]
+    fn filt_septic_str_quotes_values() {
+        assert_eq!(
+            filt_septic_str(Value::from("Well N1")).unwrap(),
+            "\"Well N1\""
+        );
+        assert_eq!(
+            filt_septic_str(Value::from("12\" pipe\r\nnorth")).unwrap(),
+            "\"12' pipe north\""
+        );
+        assert_eq!(filt_septic_str(Value::from(1.5)).unwrap(), "\"1.5\"");
+        assert_eq!(filt_septic_str(Value::from(())).unwrap(), "\"\"");
+        assert!(filt_septic_str(Value::UNDEFINED).is_err());
+        assert!(filt_septic_str(Value::from(vec![1])).is_err());
+    }
+
+    #[test]
+    fn filt_septic_bool_renders_on_off() {
+        for on in [
+            Value::from(true),
+            Value::from(1),
+            Value::from("on"),
+            Value::from(" Yes"),
+            Value::from("TRUE"),
+        ] {
+            assert_eq!(filt_septic_bool(on).unwrap(), "ON");
+        }
+        for off in [
+            Value::from(false),
+            Value::from(0.0),
+            Value::from("OFF"),
+            Value::from("no"),
+            Value::from("0"),
+        ] {
+            assert_eq!(filt_septic_bool(off).unwrap(), "OFF");
+        }
+        assert!(
+            filt_septic_bool(Value::from("maybe"))
+                .unwrap_err()
+                .to_string()
+                .contains("cannot read 'maybe' as a boolean")
+        );
+        assert!(filt_septic_bool(Value::UNDEFINED).is_err());
+    }
+
+    #[test]
+    fn filt_septic_num_never_uses_exponent() {
+        let num = |value: Value, decimals: Option<usize>, digits: Option<usize>| {
+            septic_num(&value, decimals, digits)
+        };
+        assert_eq!(num(Value::from(42), None, None).unwrap(), "42");
+        assert_eq!(num(Value::from(0.1), None, None).unwrap(), "0.1");
+        assert_eq!(num(Value::from(1e-7), None, None).unwrap(), "0.0000001");
+        assert_eq!(
+            num(Value::from(1e20), None, None).unwrap(),
+            "100000000000000000000"
+        );
+        assert_eq!(num(Value::from(2.0), None, None).unwrap(), "2");
+        assert_eq!(num(Value::from("12.50"), None, None).unwrap(), "12.5");
+
+        assert_eq!(num(Value::from(1.23456), Some(2), None).unwrap(), "1.23");
+        assert_eq!(num(Value::from(3), Some(1), None).unwrap(), "3.0");
+        assert_eq!(num(Value::from(-0.001), Some(2), None).unwrap(), "0.00");
+
+        assert_eq!(num(Value::from(1.23456), None, Some(3)).unwrap(), "1.23");
+        assert_eq!(
+            num(Value::from(0.000123456), None, Some(2)).unwrap(),
+            "0.00012"
+        );
+        assert_eq!(num(Value::from(123456), None, Some(2)).unwrap(), "120000");
+        assert_eq!(num(Value::from(-98.76), None, Some(1)).unwrap(), "-100");
+        assert_eq!(num(Value::from(0), None, Some(3)).unwrap(), "0.00");
+        assert_eq!(num(Value::from(9.99), None, Some(2)).unwrap(), "10");
+        assert_eq!(num(Value::from(99.96), None, Some(3)).unwrap(), "100");
+        assert_eq!(num(Value::from(-0.0999), None, Some(2)).unwrap(), "-0.10");
+        assert_eq!(num(Value::from(9960), None, Some(2)).unwrap(), "10000");
+
+        assert!(num(Value::from(1), Some(1), Some(1)).is_err());
+        assert!(num(Value::from(1), None, Some(0)).is_err());
+        assert!(num(Value::from(f64::NAN), None, None).is_err());
+        assert!(
+            num(Value::from("ten"), None, None)
+                .unwrap_err()
+                .to_string()
+                .contains("cannot read 'ten' as a number")
+        );
+        assert!(num(Value::UNDEFINED, None, None).is_err());
+    }
+
+    #[test]
+    fn filt_septic_num_takes_decimals_and_digits() {
+        let mut env = Environment::new();
+        env.add_filter("septic_num", filt_septic_num);
+        let render = |template: &str| env.render_str(template, context!(x => 1234.5678));
+        assert_eq!(render("{{ x | septic_num }}").unwrap(), "1234.5678");
+        assert_eq!(render("{{ x | septic_num(1) }}").unwrap(), "1234.6");
+        assert_eq!(render("{{ x | septic_num(digits=2) }}").unwrap(), "1200");
+        assert!(render("{{ x | septic_num(size=2) }}").is_err());
+    }
+
+    #[test]
+    fn filt_septic_list_counts_and_quotes_items() {
+        assert_eq!(
+            filt_septic_list(Value::from(vec!["a", "b", "c"])).unwrap(),
+            "3  \"a\"  \"b\"  \"c\""
+        );
+        assert_eq!(
+            filt_septic_list(Value::from(vec![1.5, 2.0, 1e-6])).unwrap(),
+            "3  1.5  2  0.000001"
+        );
+        assert_eq!(
+            filt_septic_list(Value::from(vec![
+                Value::from("x"),
+                Value::from(1),
+                Value::from(true)
+            ]))
+            .unwrap(),
+            "3  \"x\"  1  ON"
+        );
+        assert_eq!(
+            filt_septic_list(Value::from(Vec::<i32>::new())).unwrap(),
+            "0"
+        );
+        assert!(
+            filt_septic_list(Value::from("abc"))
+                .unwrap_err()
+                .to_string()
+                .contains("as a list")
+        );
+        assert!(filt_septic_list(Value::UNDEFINED).is_err());
+    }
+
+    #[test]
+    fn filt_septic_key_aligns_to_key_column() {
+        assert_eq!(filt_septic_key("Text1"), "         Text1=");
+        assert_eq!(filt_septic_key("Text1").len(), KEY_COLUMN);
+        assert_eq!(filt_septic_key("VeryLongKeyName"), "VeryLongKeyName=");
+    }
+
+    #[test]
+    fn set_macros_validates_namespaces() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["mvr.jinja", "std-mvr.jinja", "other/mvr.tmpl"] {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let mut renderer = MiniJinja::new(&[]).unwrap();
+        renderer.set_macros(dir.path(), &["mvr.jinja"]).unwrap();
+        assert_eq!(renderer.macros[0].namespace, "mvr");
+
+        let error = |filenames: &[&str]| {
+            MiniJinja::new(&[])
+                .unwrap()
+                .set_macros(dir.path(), filenames)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error(&["std-mvr.jinja"]).contains("cannot be imported as 'std-mvr'"));
+        assert!(
+            error(&["mvr.jinja", "other/mvr.tmpl"]).contains("would both be imported as 'mvr'")
+        );
+        assert!(error(&["missing.jinja"]).contains("not found"));
+    }
+
+    #[test]
+    fn load_template_reports_path_and_cause() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(
+            load_template(&dir.path().join("missing.tmpl"), "utf-8")
+                .unwrap()
+                .is_none()
+        );
+
+        let path = dir.path().join("template.tmpl");
+        std::fs::write(&path, b"Text1= \"\xe6\xf8\xe5\"").unwrap();
+        assert_eq!(
+            load_template(&path, "windows-1252").unwrap().unwrap(),
+            "Text1= \"\u{e6}\u{f8}\u{e5}\""
+        );
+        let err = load_template(&path, "utf-8").unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "cannot read template file '{}': not valid UTF-8 (line 1)",
+            path.display()
+        )));
+        assert!(
+            load_template(&path, "klingon")
+                .unwrap_err()
+                .to_string()
+                .contains("unknown encoding 'klingon'")
+        );
+
+        // A directory can be opened but not read
+        let err = load_template(dir.path(), "utf-8").unwrap_err();
+        assert!(err.to_string().contains("cannot read template file"));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn customfunction_lookup() {
+        let mut row = HashMap::new();
+        row.insert("x".to_string(), CtxDataType::Int(10));
+        row.insert(
+            META_COLUMN.to_string(),
+            CtxDataType::Map(IndexMap::from([(
+                "file".to_string(),
+                CtxDataType::String("coords.csv".to_string()),
+            )])),
+        );
+        let mut rows = DataSourceRows::new();
+        rows.insert("W1".to_string(), row);
+        let mut renderer = MiniJinja::new(&[]).unwrap();
+        renderer.set_sources(&HashMap::from([("coords".to_string(), rows)]));
+
+        let render = |template: &str| renderer.env.render_str(template, context! {});
+        assert_eq!(render("{{ lookup('coords', 'W1', 'x') }}").unwrap(), "10");
+        assert_eq!(render("{{ lookup('coords', 'W1').x }}").unwrap(), "10");
+        assert_eq!(
+            render("{{ lookup('coords', 'W1') }}").unwrap(),
+            "{\"x\": 10}"
+        );
+        assert_eq!(
+            render("{{ lookup('coords', 'W9', 'x') | default(0) }}").unwrap(),
+            "0"
+        );
+        assert!(
+            render("{{ lookup('nosuch', 'W1', 'x') }}")
+                .unwrap_err()
+                .to_string()
+                .contains("unknown source 'nosuch'")
+        );
+    }
+
+    #[test]
+    fn render_template_group_by() {
+        let mut rows = DataSourceRows::new();
+        for (tag, manifold) in [("FI1", "M1"), ("FI2", "M2"), ("PI1", "M1"), ("TI1", "M2")] {
+            let row = HashMap::from([
+                ("tag".to_string(), CtxDataType::String(tag.to_string())),
+                (
+                    "manifold".to_string(),
+                    CtxDataType::String(manifold.to_string()),
+                ),
+            ]);
+            rows.insert(tag.to_string(), row);
+        }
+        let mut renderer = MiniJinja::new(&[]).unwrap();
+        renderer
+            .env
+            .add_template(
+                "manifold.tmpl",
+                "{{ group }}:{% for row in rows %} {{ row.tag }}{% endfor %};",
+            )
+            .unwrap();
+        let template = config::Template {
+            name: "manifold.tmpl".to_string(),
+            source: Some("tags".to_string()),
+            group_by: Some("manifold".to_string()),
+            exclude: Some(vec![config::Include::Element("FI2".to_string())]),
+            ..Default::default()
+        };
+        let (rendered, origins) = renderer
+            .render_template_with_origins(
+                &template,
+                &HashMap::from([("tags".to_string(), rows.clone())]),
+                false,
+            )
+            .unwrap();
+        assert_eq!(rendered, "M1: FI1 PI1;M2: TI1;");
+        assert_eq!(origins.len(), 2);
+        assert_eq!(origins[1].row.as_deref(), Some("M2"));
+
+        let template = config::Template {
+            group_by: Some("manfold".to_string()),
+            ..template
+        };
+        let error = renderer
+            .render_template(
+                &template,
+                &HashMap::from([("tags".to_string(), rows)]),
+                false,
+            )
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unknown column 'manfold' in group_by of manifold.tmpl")
+        );
+    }
+
+    fn render_strict(template: &str, strict: bool) -> anyhow::Result<String> {
+        let mut renderer = MiniJinja::new(&[]).unwrap();
+        renderer.set_strict(strict);
+        renderer.env.add_template("strict.tmpl", template).unwrap();
+        renderer.render("strict.tmpl", context!(row => context!(tag => "FI1")))
+    }
+
+    fn strict_error(template: &str) -> String {
+        let err = render_strict(template, true).unwrap_err();
+        error_message(err.downcast_ref::<Error>().unwrap())
+    }
+
+    #[test]
+    fn strict_mode_fails_on_any_undefined_use() {
+        assert_eq!(
+            render_strict("{% if row.unit %}x{% endif %}", false).unwrap(),
+            ""
+        );
+        assert_eq!(render_strict("{{ row.unit | upper }}", false).unwrap(), "");
+
+        assert_eq!(
+            strict_error("line 1\n{% if row.unit %}x{% endif %}"),
+            "undefined value: 'row.unit' (in strict.tmpl:2)"
+        );
+        assert_eq!(
+            strict_error("{{ row.unit | trim | upper }}"),
+            "undefined value: 'row.unit | trim' (in strict.tmpl:1)"
+        );
+        assert_eq!(
+            strict_error("{{ rows[0].tag }}"),
+            "undefined value: 'rows[0]' (in strict.tmpl:1)"
+        );
+        assert_eq!(render_strict("{{ row.tag }}", true).unwrap(), "FI1");
+        assert_eq!(
+            render_strict("{{ row.unit is defined }}", true).unwrap(),
+            "false"
+        );
+        assert_eq!(
+            render_strict("{{ row.unit | default('-') }}", true).unwrap(),
+            "-"
+        );
+    }
+
+    #[test]
+    fn filt_unpack_strict_fails_on_invalid_key() {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_filter("unpack", filt_unpack);
+        let result = env.render_str(
+            "{{ A | unpack('a', 'c') }}",
+            context!(A => vec![context!(a => "aa", b => "bb")]),
+        );
+        assert_eq!(result.unwrap_err().detail(), Some("key 'c' not found"));
+    }
 }
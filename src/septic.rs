@@ -0,0 +1,485 @@
+use anyhow::{Result, bail};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::LazyLock;
+
+/// Column at which the `=` of an attribute key is right-aligned in generated configs
+pub const KEY_COLUMN: usize = 15;
+
+const LINE_ENDING: &str = "\r\n";
+
+static HEADER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*([A-Za-z][A-Za-z0-9_]*):(?:\s+(.*?))?\s*$").unwrap());
+static ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*([A-Za-z][A-Za-z0-9_]*)=\s*(.*?)\s*$").unwrap());
+
+/// Inclusive range of 1-based line numbers in the parsed text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A single token in an attribute value
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    /// Double-quoted string, stored without the quotes
+    Str(String),
+    /// Any other bare word, e.g. `ON`, `ACTIVE` or a bitmask like `0001`
+    Ident(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{x}"),
+            Value::Str(s) => write!(f, "\"{s}\""),
+            Value::Ident(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl Value {
+    fn from_bare(word: &str) -> Self {
+        // Leading zeros are significant in bitmasks, so keep those as identifiers
+        if word.len() > 1 && word.starts_with('0') && word.bytes().all(|b| b.is_ascii_digit()) {
+            return Value::Ident(word.to_string());
+        }
+        if let Ok(i) = word.parse::<i64>() {
+            Value::Int(i)
+        } else if let Ok(x) = word.parse::<f64>() {
+            Value::Float(x)
+        } else {
+            Value::Ident(word.to_string())
+        }
+    }
+
+    /// Split raw attribute text into tokens. Quoted strings may contain whitespace.
+    pub fn tokenize(text: &str) -> Vec<Value> {
+        let mut values = Vec::new();
+        let mut chars = text.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '"' {
+                chars.next();
+                let s: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                values.push(Value::Str(s));
+            } else {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                values.push(Value::from_bare(&word));
+            }
+        }
+        values
+    }
+}
+
+/// A `Key=  value` line, including any continuation lines that follow it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Attribute {
+    pub key: String,
+    /// The value text with surrounding whitespace removed. Continuation lines are joined with '\n'.
+    pub value: String,
+    pub span: Span,
+    #[serde(skip)]
+    raw: String,
+}
+
+impl Attribute {
+    /// Create an attribute formatted with standard Septic alignment
+    pub fn new(key: &str, value: &str) -> Self {
+        let mut lines = value.lines();
+        let mut raw = format!(
+            "{:>width$}  {}{LINE_ENDING}",
+            format!("{key}="),
+            lines.next().unwrap_or_default(),
+            width = KEY_COLUMN
+        );
+        for line in lines {
+            raw.push_str(&format!(
+                "{:width$}{}{LINE_ENDING}",
+                "",
+                line.trim(),
+                width = KEY_COLUMN + 2
+            ));
+        }
+        Self {
+            key: key.to_string(),
+            value: value.to_string(),
+            span: Span::default(),
+            raw,
+        }
+    }
+
+    /// The value split into typed tokens
+    pub fn values(&self) -> Vec<Value> {
+        Value::tokenize(&self.value)
+    }
+}
+
+/// Content in an object body
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Entry {
+    Attribute(Attribute),
+    /// Blank or comment line
+    Trivia(String),
+}
+
+/// A Septic object such as `SopcProc:`, `Mvr:` or `DisplayGroup:`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Object {
+    pub kind: String,
+    pub name: String,
+    /// Lines from the header to the last attribute
+    pub span: Span,
+    /// Index of the enclosing object in `Document::objects`
+    pub parent: Option<usize>,
+    pub entries: Vec<Entry>,
+    #[serde(skip)]
+    header: String,
+}
+
+impl Object {
+    /// Create an object with a header formatted with standard Septic alignment
+    pub fn new(kind: &str, name: &str) -> Self {
+        let label = format!("{kind}:");
+        let padding = KEY_COLUMN.saturating_sub(label.len()).max(2);
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            span: Span::default(),
+            parent: None,
+            entries: Vec::new(),
+            header: format!("  {label}{:padding$}{name}{LINE_ENDING}", ""),
+        }
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = &Attribute> {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::Attribute(attr) => Some(attr),
+            Entry::Trivia(_) => None,
+        })
+    }
+
+    /// First attribute with the given key
+    pub fn get(&self, key: &str) -> Option<&Attribute> {
+        self.attributes().find(|attr| attr.key == key)
+    }
+
+    pub fn push_attribute(&mut self, attribute: Attribute) {
+        self.entries.push(Entry::Attribute(attribute));
+    }
+}
+
+/// Nesting depth used to build the object tree. Processes and applications are roots, display
+/// groups and models belong to an application, and everything else belongs to the closest
+/// preceding object of lower depth.
+fn nesting_level(kind: &str) -> usize {
+    match kind {
+        "System" | "SopcProc" | "DmmyAppl" | "SmpcAppl" | "ExprProc" => 0,
+        "DisplayGroup" | "ExprModl" | "CalcModl" => 1,
+        _ => 2,
+    }
+}
+
+/// A parsed Septic config
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Document {
+    /// Blank or comment lines before the first object
+    pub preamble: Vec<String>,
+    pub objects: Vec<Object>,
+}
+
+impl Document {
+    #[allow(clippy::missing_errors_doc)]
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut doc = Document::default();
+        let mut stack: Vec<usize> = Vec::new();
+
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let line_num = i + 1;
+            let trimmed = line.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with("//") || trimmed.starts_with('%') {
+                match doc.objects.last_mut() {
+                    Some(obj) => obj.entries.push(Entry::Trivia(line.to_string())),
+                    None => doc.preamble.push(line.to_string()),
+                }
+            } else if let Some(caps) = HEADER_RE.captures(line) {
+                let kind = caps[1].to_string();
+                let level = nesting_level(&kind);
+                while let Some(&top) = stack.last() {
+                    if nesting_level(&doc.objects[top].kind) >= level {
+                        stack.pop();
+                    } else {
+                        break;
+                    }
+                }
+                doc.objects.push(Object {
+                    kind,
+                    name: caps.get(2).map_or("", |m| m.as_str()).to_string(),
+                    span: Span {
+                        start: line_num,
+                        end: line_num,
+                    },
+                    parent: stack.last().copied(),
+                    entries: Vec::new(),
+                    header: line.to_string(),
+                });
+                stack.push(doc.objects.len() - 1);
+            } else {
+                let Some(obj) = doc.objects.last_mut() else {
+                    bail!("line {line_num}: expected object header, found '{trimmed}'");
+                };
+                if let Some(caps) = ATTRIBUTE_RE.captures(line) {
+                    obj.entries.push(Entry::Attribute(Attribute {
+                        key: caps[1].to_string(),
+                        value: caps[2].to_string(),
+                        span: Span {
+                            start: line_num,
+                            end: line_num,
+                        },
+                        raw: line.to_string(),
+                    }));
+                } else if let Some(Entry::Attribute(attr)) = obj.entries.last_mut() {
+                    attr.value.push('\n');
+                    attr.value.push_str(trimmed);
+                    attr.span.end = line_num;
+                    attr.raw.push_str(line);
+                } else {
+                    bail!("line {line_num}: unexpected value '{trimmed}' without attribute key");
+                }
+                obj.span.end = line_num;
+            }
+        }
+        Ok(doc)
+    }
+
+    /// Objects without a parent, in document order
+    pub fn roots(&self) -> impl Iterator<Item = (usize, &Object)> {
+        self.objects
+            .iter()
+            .enumerate()
+            .filter(|(_, obj)| obj.parent.is_none())
+    }
+
+    /// Direct children of the object at `index`, in document order
+    pub fn children(&self, index: usize) -> impl Iterator<Item = (usize, &Object)> {
+        self.objects
+            .iter()
+            .enumerate()
+            .filter(move |(_, obj)| obj.parent == Some(index))
+    }
+
+    /// First object with the given kind and name
+    pub fn find(&self, kind: &str, name: &str) -> Option<&Object> {
+        self.objects
+            .iter()
+            .find(|obj| obj.kind == kind && obj.name == name)
+    }
+
+    /// The object whose span contains the given line
+    pub fn object_at_line(&self, line_num: usize) -> Option<&Object> {
+        self.objects
+            .iter()
+            .find(|obj| obj.span.start <= line_num && line_num <= obj.span.end)
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.header)?;
+        for entry in &self.entries {
+            match entry {
+                Entry::Attribute(attr) => f.write_str(&attr.raw)?,
+                Entry::Trivia(line) => f.write_str(line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.preamble {
+            f.write_str(line)?;
+        }
+        for obj in &self.objects {
+            write!(f, "{obj}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const SAMPLE: &str = "// Generated config\r
+\r
+  SmpcAppl:      myappl\r
+         Text1=  \"My application\"\r
+          Grps=  3\r
+                 \"Tables\"  \"Overview\"  \"Group3\"\r
+\r
+  Mvr:           D01Zpc\r
+       GrpMask=  0000000000000000000001000000000\r
+         MaxUp=  0.5\r
+          Mode=  ACTIVE\r
+\r
+  DisplayGroup:  Tables\r
+       GroupNo=  1\r
+\r
+  Table:         MPCtable\r
+           Row=  1\r
+";
+
+    #[test]
+    fn parse_objects_and_attributes() {
+        let doc = Document::parse(SAMPLE).unwrap();
+        assert_eq!(doc.preamble.len(), 2);
+        let kinds: Vec<_> = doc.objects.iter().map(|o| o.kind.as_str()).collect();
+        assert_eq!(kinds, ["SmpcAppl", "Mvr", "DisplayGroup", "Table"]);
+
+        let appl = &doc.objects[0];
+        assert_eq!(appl.name, "myappl");
+        assert_eq!(appl.span, Span { start: 3, end: 6 });
+        let grps = appl.get("Grps").unwrap();
+        assert_eq!(grps.span, Span { start: 5, end: 6 });
+        assert_eq!(grps.value, "3\n\"Tables\"  \"Overview\"  \"Group3\"");
+
+        let mvr = doc.find("Mvr", "D01Zpc").unwrap();
+        let keys: Vec<_> = mvr.attributes().map(|a| a.key.as_str()).collect();
+        assert_eq!(keys, ["GrpMask", "MaxUp", "Mode"]);
+    }
+
+    #[test]
+    fn parse_treats_percent_lines_as_comments() {
+        let text = "% From wells.xlsx, sheet Wells, row 3\r\n  Mvr:           D01Zpc\r\n         MaxUp=  0.5\r\n% row 4\r\n          Mode=  ACTIVE\r\n";
+        let doc = Document::parse(text).unwrap();
+        assert_eq!(doc.preamble, ["% From wells.xlsx, sheet Wells, row 3\r\n"]);
+        let mvr = &doc.objects[0];
+        assert_eq!(mvr.get("MaxUp").unwrap().value, "0.5");
+        assert_eq!(mvr.entries[1], Entry::Trivia("% row 4\r\n".to_string()));
+    }
+
+    #[test]
+    fn parse_builds_tree() {
+        let doc = Document::parse(SAMPLE).unwrap();
+        assert_eq!(doc.roots().count(), 1);
+        let children: Vec<_> = doc.children(0).map(|(_, o)| o.name.as_str()).collect();
+        assert_eq!(children, ["D01Zpc", "Tables"]);
+        assert_eq!(doc.objects[3].parent, Some(2));
+    }
+
+    #[test]
+    fn attribute_values_are_typed() {
+        let doc = Document::parse(SAMPLE).unwrap();
+        let appl = &doc.objects[0];
+        assert_eq!(
+            appl.get("Grps").unwrap().values(),
+            vec![
+                Value::Int(3),
+                Value::Str("Tables".to_string()),
+                Value::Str("Overview".to_string()),
+                Value::Str("Group3".to_string())
+            ]
+        );
+        let mvr = &doc.objects[1];
+        assert_eq!(
+            mvr.get("GrpMask").unwrap().values(),
+            vec![Value::Ident("0000000000000000000001000000000".to_string())]
+        );
+        assert_eq!(mvr.get("MaxUp").unwrap().values(), vec![Value::Float(0.5)]);
+        assert_eq!(
+            mvr.get("Mode").unwrap().values(),
+            vec![Value::Ident("ACTIVE".to_string())]
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_whitespace_in_strings() {
+        assert_eq!(
+            Value::tokenize("2  \"Text with space\" \"\""),
+            vec![
+                Value::Int(2),
+                Value::Str("Text with space".to_string()),
+                Value::Str(String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn object_at_line_finds_enclosing_object() {
+        let doc = Document::parse(SAMPLE).unwrap();
+        assert_eq!(doc.object_at_line(10).unwrap().name, "D01Zpc");
+        assert!(doc.object_at_line(1).is_none());
+    }
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let doc = Document::parse(SAMPLE).unwrap();
+        assert_eq!(doc.to_string(), SAMPLE);
+    }
+
+    #[test]
+    fn round_trip_basic_example() {
+        let mut bytes = Vec::new();
+        std::fs::File::open("docs/basic example/example.cnfg")
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let (text, _, _) = encoding_rs::WINDOWS_1252.decode(&bytes);
+        let doc = Document::parse(&text).unwrap();
+        assert!(doc.find("System", "CVMVtest").is_some());
+        assert_eq!(doc.to_string(), text);
+    }
+
+    #[test]
+    fn new_objects_use_standard_alignment() {
+        let mut obj = Object::new("Mvr", "D01Zpc");
+        obj.push_attribute(Attribute::new("Text1", "\"Choke\""));
+        obj.push_attribute(Attribute::new("Xvrs", "2\n\"a\"  \"b\""));
+        assert_eq!(
+            obj.to_string(),
+            "  Mvr:           D01Zpc\r\n         Text1=  \"Choke\"\r\n          Xvrs=  2\r\n                 \"a\"  \"b\"\r\n"
+        );
+        let doc = Document::parse(&obj.to_string()).unwrap();
+        assert_eq!(
+            doc.objects[0].get("Xvrs").unwrap().value,
+            obj.get("Xvrs").unwrap().value
+        );
+    }
+
+    #[test]
+    fn fail_parse_on_content_before_header() {
+        let result = Document::parse("  Text1=  \"x\"\n");
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("line 1: expected object header")
+        );
+    }
+
+    #[test]
+    fn fail_parse_on_value_without_key() {
+        let result = Document::parse("  Mvr:  x\n   \"dangling\"\n");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("line 2"));
+    }
+}
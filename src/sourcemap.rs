@@ -0,0 +1,167 @@
+use crate::renderer::Origin;
+use crate::septic::Span;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The template and source row that produced a range of lines in the output
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceMapEntry {
+    pub lines: Span,
+    pub template: String,
+    pub source: Option<String>,
+    pub row: Option<String>,
+}
+
+impl fmt::Display for SourceMapEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.source, &self.row) {
+            (Some(source), Some(row)) => write!(f, "{}, {source} row '{row}'", self.template),
+            _ => write!(f, "{}", self.template),
+        }
+    }
+}
+
+/// Map from line ranges in a generated file to the templates and source rows that produced them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub entries: Vec<SourceMapEntry>,
+}
+
+impl SourceMap {
+    /// Build from the origins recorded while rendering. Origins that produced no lines are left out.
+    pub fn new(rendered: &str, origins: &[Origin]) -> Self {
+        let line_count = rendered.split_inclusive('\n').count();
+        // Origins are in output order, so lines are counted from the previous offset
+        let (mut counted_to, mut newlines) = (0, 0);
+        let mut start_line = |offset: usize| {
+            let offset = offset.min(rendered.len());
+            if offset < counted_to {
+                (counted_to, newlines) = (0, 0);
+            }
+            newlines += rendered.as_bytes()[counted_to..offset]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count();
+            counted_to = offset;
+            newlines + 1
+        };
+
+        let mut entries: Vec<SourceMapEntry> = Vec::new();
+        for origin in origins {
+            let start = start_line(origin.offset);
+            if start > line_count {
+                continue;
+            }
+            if let Some(previous) = entries.last_mut() {
+                if previous.lines.start == start {
+                    entries.pop();
+                } else {
+                    previous.lines.end = start - 1;
+                }
+            }
+            entries.push(SourceMapEntry {
+                lines: Span {
+                    start,
+                    end: line_count,
+                },
+                template: origin.template.clone(),
+                source: origin.source.clone(),
+                row: origin.row.clone(),
+            });
+        }
+        Self { entries }
+    }
+
+    /// Location of the source map for a generated file, e.g. `example.cnfg.map.json`
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".map.json");
+        PathBuf::from(path)
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Problem reading source map '{}'", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Problem parsing source map '{}'", path.display()))
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Problem writing source map '{}'", path.display()))
+    }
+
+    /// The entry that contains the given 1-based line number
+    pub fn entry_for_line(&self, line_num: usize) -> Option<&SourceMapEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.lines.start <= line_num && line_num <= entry.lines.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(template: &str, row: Option<&str>, offset: usize) -> Origin {
+        Origin {
+            template: template.to_string(),
+            source: row.map(|_| "main".to_string()),
+            row: row.map(ToString::to_string),
+            offset,
+        }
+    }
+
+    #[test]
+    fn sourcemap_assigns_line_ranges() {
+        let rendered = "a1\na2\nb1\nb2\nc1\n";
+        let origins = [
+            origin("a", None, 0),
+            origin("b", Some("one"), 6),
+            origin("c", Some("two"), 12),
+        ];
+        let map = SourceMap::new(rendered, &origins);
+        let ranges: Vec<_> = map
+            .entries
+            .iter()
+            .map(|e| (e.lines.start, e.lines.end))
+            .collect();
+        assert_eq!(ranges, [(1, 2), (3, 4), (5, 5)]);
+        assert_eq!(
+            map.entry_for_line(4).unwrap().to_string(),
+            "b, main row 'one'"
+        );
+        assert_eq!(map.entry_for_line(1).unwrap().to_string(), "a");
+        assert!(map.entry_for_line(6).is_none());
+    }
+
+    #[test]
+    fn sourcemap_skips_origins_without_output() {
+        let rendered = "a1\nc1\n";
+        let origins = [
+            origin("a", None, 0),
+            origin("b", None, 3),
+            origin("c", None, 3),
+            origin("d", None, 6),
+        ];
+        let map = SourceMap::new(rendered, &origins);
+        let templates: Vec<_> = map.entries.iter().map(|e| e.template.as_str()).collect();
+        assert_eq!(templates, ["a", "c"]);
+    }
+
+    #[test]
+    fn sourcemap_write_and_read() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = SourceMap::path_for(&dir.path().join("out.cnfg"));
+        assert!(path.ends_with("out.cnfg.map.json"));
+        let map = SourceMap::new("a1\n", &[origin("a", Some("one"), 0)]);
+        map.write(&path)?;
+        assert_eq!(SourceMap::read(&path)?, map);
+        Ok(())
+    }
+}